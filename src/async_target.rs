@@ -0,0 +1,540 @@
+use std::collections::VecDeque;
+use std::error::Error;
+use std::io;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::{Entry, Severity, Target};
+
+// what to do with an entry logged while the queue is full
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    // wait for the worker to make room
+    Block,
+    DropNewest,
+    DropOldest,
+    // drop entries less severe than the one given, and wait for room for
+    // the rest
+    DropBelow(Severity),
+}
+
+// logs to the wrapped target on a thread of its own, so that callers only
+// wait on the network or disk if the queue fills up
+pub struct AsyncTarget {
+    shared: Arc<Shared>,
+    worker: Option<JoinHandle<()>>,
+    drain_timeout: Duration,
+}
+
+pub struct AsyncTargetBuilder {
+    target: Box<dyn Target + Send>,
+    capacity: usize,
+    overflow_policy: OverflowPolicy,
+    drain_timeout: Duration,
+}
+
+struct Shared {
+    queue: Mutex<Queue>,
+    // signalled when an entry is queued or the target is shut down
+    queued: Condvar,
+    // signalled when the worker finishes with an entry
+    done: Condvar,
+    capacity: usize,
+    overflow_policy: OverflowPolicy,
+}
+
+struct Queue {
+    entries: VecDeque<Entry>,
+    in_flight: bool,
+    shutdown: bool,
+    // cleared when the worker exits, including if the wrapped target panics
+    worker_alive: bool,
+    worker_panicked: bool,
+    dropped: u64,
+    failed: u64,
+}
+
+impl AsyncTarget {
+    pub fn new(target: impl Target + Send + 'static) -> Result<Self, Box<dyn Error>> {
+        AsyncTarget::builder(target).build()
+    }
+
+    pub fn builder(target: impl Target + Send + 'static) -> AsyncTargetBuilder {
+        AsyncTargetBuilder {
+            target: Box::new(target),
+            capacity: 1024,
+            overflow_policy: OverflowPolicy::Block,
+            drain_timeout: Duration::from_secs(5),
+        }
+    }
+
+    // entries thrown away by the overflow policy, or left in the queue when
+    // the target was dropped
+    pub fn dropped(&self) -> u64 {
+        self.shared.lock().dropped
+    }
+
+    // entries the wrapped target returned an error for
+    pub fn failed(&self) -> u64 {
+        self.shared.lock().failed
+    }
+
+    pub fn queued(&self) -> usize {
+        self.shared.lock().entries.len()
+    }
+
+    // waits until every entry queued so far has been logged, or fails if
+    // the worker has stopped and they never will be
+    pub fn flush(&self) -> Result<(), Box<dyn Error>> {
+        let mut queue = self.shared.lock();
+
+        while (!queue.entries.is_empty() || queue.in_flight) && queue.worker_alive {
+            queue = self.shared.done.wait(queue).unwrap();
+        }
+
+        if queue.worker_panicked || !queue.entries.is_empty() {
+            Err(worker_stopped())
+        } else {
+            Ok(())
+        }
+    }
+
+    // stops taking entries, leaving the worker to log the ones already
+    // queued and then exit
+    pub(crate) fn close(&self) {
+        self.shared.lock().shutdown = true;
+        self.shared.queued.notify_all();
+        self.shared.done.notify_all();
+    }
+
+    // queues an entry, handing it back rather than waiting if there is no
+    // room for it and wait is false
+    pub(crate) fn enqueue(
+        &self,
+        entry: Entry,
+        wait: bool,
+    ) -> Result<Option<Entry>, Box<dyn Error>> {
+        let shared = &self.shared;
+        let mut queue = shared.lock();
+
+        if queue.entries.len() >= shared.capacity && !queue.shutdown && queue.worker_alive {
+            let block = match shared.overflow_policy {
+                OverflowPolicy::Block => true,
+                OverflowPolicy::DropNewest => false,
+                OverflowPolicy::DropOldest => {
+                    queue.entries.pop_front();
+                    queue.dropped += 1;
+                    true
+                }
+                OverflowPolicy::DropBelow(severity) => entry.get_severity()? <= severity,
+            };
+
+            if !block {
+                queue.dropped += 1;
+                return Ok(None);
+            }
+
+            if queue.entries.len() >= shared.capacity && !wait {
+                return Ok(Some(entry));
+            }
+
+            while queue.entries.len() >= shared.capacity && !queue.shutdown && queue.worker_alive {
+                queue = shared.done.wait(queue).unwrap();
+            }
+        }
+
+        if queue.shutdown {
+            return Err(Box::new(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "the asynchronous target has shut down",
+            )));
+        }
+
+        if !queue.worker_alive {
+            return Err(worker_stopped());
+        }
+
+        queue.entries.push_back(entry);
+        shared.queued.notify_one();
+
+        Ok(None)
+    }
+}
+
+impl AsyncTargetBuilder {
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    pub fn overflow_policy(mut self, overflow_policy: OverflowPolicy) -> Self {
+        self.overflow_policy = overflow_policy;
+        self
+    }
+
+    // how long dropping the target waits for queued entries to be logged
+    pub fn drain_timeout(mut self, drain_timeout: Duration) -> Self {
+        self.drain_timeout = drain_timeout;
+        self
+    }
+
+    pub fn build(self) -> Result<AsyncTarget, Box<dyn Error>> {
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(Queue {
+                entries: VecDeque::with_capacity(self.capacity),
+                in_flight: false,
+                shutdown: false,
+                worker_alive: true,
+                worker_panicked: false,
+                dropped: 0,
+                failed: 0,
+            }),
+            queued: Condvar::new(),
+            done: Condvar::new(),
+            capacity: self.capacity,
+            overflow_policy: self.overflow_policy,
+        });
+
+        let worker_shared = shared.clone();
+        let target = self.target;
+        let worker = thread::Builder::new()
+            .name(String::from("stumpless-async"))
+            .spawn(move || run_worker(&worker_shared, &*target))?;
+
+        Ok(AsyncTarget {
+            shared,
+            worker: Some(worker),
+            drain_timeout: self.drain_timeout,
+        })
+    }
+}
+
+impl Target for AsyncTarget {
+    // entries are logged later, so nothing has been written when this returns
+    fn log(&self, entry: &Entry) -> Result<usize, Box<dyn Error>> {
+        self.enqueue(entry.try_clone()?, true)?;
+        Ok(0)
+    }
+}
+
+impl Drop for AsyncTarget {
+    fn drop(&mut self) {
+        let deadline = Instant::now() + self.drain_timeout;
+        self.close();
+        let mut queue = self.shared.lock();
+
+        while (!queue.entries.is_empty() || queue.in_flight) && queue.worker_alive {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+
+            queue = self
+                .shared
+                .done
+                .wait_timeout(queue, deadline - now)
+                .unwrap()
+                .0;
+        }
+
+        // a worker stuck on a slow target is left to finish on its own
+        // rather than holding up the caller any longer
+        let drained = !queue.worker_alive || (queue.entries.is_empty() && !queue.in_flight);
+        queue.dropped += queue.entries.len() as u64;
+        queue.entries.clear();
+        drop(queue);
+
+        if drained {
+            if let Some(worker) = self.worker.take() {
+                let _ = worker.join();
+            }
+        }
+    }
+}
+
+impl Shared {
+    // a panic in the wrapped target happens with the lock released, but the
+    // queue is still usable if one somehow poisons it
+    fn lock(&self) -> MutexGuard<'_, Queue> {
+        self.queue.lock().unwrap_or_else(|error| error.into_inner())
+    }
+}
+
+// marks the worker as gone however it exits, so that callers waiting on it
+// are woken rather than left to wait forever
+struct WorkerGuard<'a>(&'a Shared);
+
+impl Drop for WorkerGuard<'_> {
+    fn drop(&mut self) {
+        let mut queue = self.0.lock();
+        queue.worker_alive = false;
+        queue.worker_panicked = thread::panicking();
+
+        if queue.in_flight {
+            queue.in_flight = false;
+            queue.failed += 1;
+        }
+
+        self.0.done.notify_all();
+    }
+}
+
+fn worker_stopped() -> Box<dyn Error> {
+    Box::new(io::Error::new(
+        io::ErrorKind::BrokenPipe,
+        "the asynchronous target's worker has stopped",
+    ))
+}
+
+fn run_worker(shared: &Shared, target: &dyn Target) {
+    let _guard = WorkerGuard(shared);
+
+    loop {
+        let entry = {
+            let mut queue = shared.lock();
+
+            while queue.entries.is_empty() && !queue.shutdown {
+                queue = shared.queued.wait(queue).unwrap();
+            }
+
+            match queue.entries.pop_front() {
+                Some(entry) => {
+                    queue.in_flight = true;
+                    entry
+                }
+                None => return,
+            }
+        };
+
+        let result = target.log(&entry);
+
+        let mut queue = shared.lock();
+        queue.in_flight = false;
+        if result.is_err() {
+            queue.failed += 1;
+        }
+        shared.done.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Facility;
+    use std::sync::mpsc::{self, Receiver};
+
+    struct Recording(Arc<Mutex<Vec<String>>>);
+
+    impl Target for Recording {
+        fn log(&self, entry: &Entry) -> Result<usize, Box<dyn Error>> {
+            let message = entry.get_message()?;
+            let length = message.len();
+            self.0.lock().unwrap().push(message);
+            Ok(length)
+        }
+    }
+
+    // panics once released, standing in for a target with a bug in it
+    struct Panicking(Receiver<()>);
+
+    impl Target for Panicking {
+        fn log(&self, _entry: &Entry) -> Result<usize, Box<dyn Error>> {
+            let _ = self.0.recv();
+            panic!("the wrapped target panicked");
+        }
+    }
+
+    // waits for a release before logging each entry, so that tests can fill
+    // the queue behind it
+    struct Blocking {
+        released: Receiver<()>,
+        messages: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Target for Blocking {
+        fn log(&self, entry: &Entry) -> Result<usize, Box<dyn Error>> {
+            let _ = self.released.recv();
+            Recording(self.messages.clone()).log(entry)
+        }
+    }
+
+    fn entry(message: &str) -> Entry {
+        Entry::new(Facility::User, Severity::Info, "app", "msgid", message).unwrap()
+    }
+
+    // a target with one entry in flight and a full queue of two behind it,
+    // along with the sender releasing the entries and what was logged
+    fn full_target(
+        overflow_policy: OverflowPolicy,
+    ) -> (AsyncTarget, mpsc::Sender<()>, Arc<Mutex<Vec<String>>>) {
+        let (release, released) = mpsc::channel();
+        let messages = Arc::new(Mutex::new(Vec::new()));
+        let target = AsyncTarget::builder(Blocking {
+            released,
+            messages: messages.clone(),
+        })
+        .capacity(2)
+        .overflow_policy(overflow_policy)
+        .build()
+        .unwrap();
+
+        target.log(&entry("in flight")).unwrap();
+        while !target.shared.lock().in_flight {
+            thread::yield_now();
+        }
+        target.log(&entry("first")).unwrap();
+        target.log(&entry("second")).unwrap();
+
+        (target, release, messages)
+    }
+
+    fn release_all(target: &AsyncTarget, release: &mpsc::Sender<()>, count: usize) {
+        for _ in 0..count {
+            release.send(()).unwrap();
+        }
+        target.flush().unwrap();
+    }
+
+    #[test]
+    fn flush_waits_for_queued_entries() {
+        let messages = Arc::new(Mutex::new(Vec::new()));
+        let target = AsyncTarget::new(Recording(messages.clone())).unwrap();
+
+        for i in 0..5 {
+            target.log(&entry(&format!("entry {}", i))).unwrap();
+        }
+        target.flush().unwrap();
+
+        assert_eq!(messages.lock().unwrap().len(), 5);
+        assert_eq!(target.queued(), 0);
+    }
+
+    #[test]
+    fn flush_fails_once_the_worker_has_stopped() {
+        let (release, released) = mpsc::channel();
+        let target = AsyncTarget::new(Panicking(released)).unwrap();
+
+        target.log(&entry("first")).unwrap();
+        release.send(()).unwrap();
+
+        assert!(target.flush().is_err());
+        assert!(target.log(&entry("second")).is_err());
+        assert_eq!(target.failed(), 1);
+    }
+
+    #[test]
+    fn blocked_log_fails_when_the_worker_stops() {
+        let (release, released) = mpsc::channel();
+        let target = AsyncTarget::builder(Panicking(released))
+            .capacity(1)
+            .build()
+            .unwrap();
+
+        target.log(&entry("in flight")).unwrap();
+        while !target.shared.lock().in_flight {
+            thread::yield_now();
+        }
+        target.log(&entry("queued")).unwrap();
+
+        // whether the worker stops before or after this starts waiting for
+        // room, it must not be left waiting forever
+        thread::scope(|scope| {
+            let blocked = scope.spawn(|| target.log(&entry("blocked")).is_err());
+            release.send(()).unwrap();
+
+            assert!(blocked.join().unwrap());
+        });
+    }
+
+    #[test]
+    fn drop_newest_discards_the_new_entry() {
+        let (target, release, messages) = full_target(OverflowPolicy::DropNewest);
+
+        target.log(&entry("third")).unwrap();
+        assert_eq!(target.dropped(), 1);
+
+        release_all(&target, &release, 3);
+        assert_eq!(*messages.lock().unwrap(), ["in flight", "first", "second"]);
+    }
+
+    #[test]
+    fn drop_oldest_discards_the_oldest_queued_entry() {
+        let (target, release, messages) = full_target(OverflowPolicy::DropOldest);
+
+        target.log(&entry("third")).unwrap();
+        assert_eq!(target.dropped(), 1);
+
+        release_all(&target, &release, 3);
+        assert_eq!(*messages.lock().unwrap(), ["in flight", "second", "third"]);
+    }
+
+    #[test]
+    fn drop_below_discards_only_less_severe_entries() {
+        let (target, release, messages) = full_target(OverflowPolicy::DropBelow(Severity::Warning));
+
+        target.log(&entry("info")).unwrap();
+        assert_eq!(target.dropped(), 1);
+
+        // an entry at least as severe as the threshold waits for room instead
+        let error = Entry::new(Facility::User, Severity::Error, "app", "msgid", "error").unwrap();
+        thread::scope(|scope| {
+            let blocked = scope.spawn(|| target.log(&error).is_ok());
+            release.send(()).unwrap();
+
+            assert!(blocked.join().unwrap());
+        });
+
+        release_all(&target, &release, 3);
+        assert_eq!(target.dropped(), 1);
+        assert_eq!(
+            *messages.lock().unwrap(),
+            ["in flight", "first", "second", "error"]
+        );
+    }
+
+    #[test]
+    fn drop_drains_the_queue() {
+        let messages = Arc::new(Mutex::new(Vec::new()));
+        let target = AsyncTarget::new(Recording(messages.clone())).unwrap();
+
+        for i in 0..5 {
+            target.log(&entry(&format!("entry {}", i))).unwrap();
+        }
+        drop(target);
+
+        assert_eq!(messages.lock().unwrap().len(), 5);
+    }
+
+    #[test]
+    fn drop_gives_up_after_the_drain_timeout() {
+        let (release, released) = mpsc::channel();
+        let messages = Arc::new(Mutex::new(Vec::new()));
+        let target = AsyncTarget::builder(Blocking {
+            released,
+            messages: messages.clone(),
+        })
+        .drain_timeout(Duration::from_millis(50))
+        .build()
+        .unwrap();
+
+        target.log(&entry("in flight")).unwrap();
+        target.log(&entry("first")).unwrap();
+        target.log(&entry("second")).unwrap();
+        let shared = target.shared.clone();
+
+        let started = Instant::now();
+        drop(target);
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        // whatever was still queued counts as dropped
+        let dropped = shared.lock().dropped;
+        assert!(dropped == 2 || dropped == 3, "{}", dropped);
+
+        drop(release);
+        while shared.lock().worker_alive {
+            thread::yield_now();
+        }
+        assert!(messages.lock().unwrap().len() <= 1);
+    }
+}
@@ -0,0 +1,44 @@
+use std::io;
+use std::os::unix::io::AsRawFd;
+
+// sends the data along with a single SOL_SOCKET control message holding the
+// given value, such as the credentials of SCM_CREDENTIALS or the descriptor
+// of SCM_RIGHTS
+pub(crate) fn send_with_control<T>(
+    socket: &impl AsRawFd,
+    data: &[u8],
+    message_type: libc::c_int,
+    value: T,
+) -> io::Result<usize> {
+    let value_size = std::mem::size_of::<T>() as u32;
+
+    // u64 storage keeps the control buffer aligned for cmsghdr
+    let control_size = unsafe { libc::CMSG_SPACE(value_size) } as usize;
+    let mut control = vec![0u64; (control_size + 7) / 8];
+
+    let mut iov = libc::iovec {
+        iov_base: data.as_ptr() as *mut libc::c_void,
+        iov_len: data.len(),
+    };
+    let mut header: libc::msghdr = unsafe { std::mem::zeroed() };
+    header.msg_iov = &mut iov;
+    header.msg_iovlen = 1;
+    header.msg_control = control.as_mut_ptr().cast();
+    header.msg_controllen = control_size as _;
+
+    let sent = unsafe {
+        let control_header = libc::CMSG_FIRSTHDR(&header);
+        (*control_header).cmsg_level = libc::SOL_SOCKET;
+        (*control_header).cmsg_type = message_type;
+        (*control_header).cmsg_len = libc::CMSG_LEN(value_size) as _;
+        std::ptr::write_unaligned(libc::CMSG_DATA(control_header) as *mut T, value);
+
+        libc::sendmsg(socket.as_raw_fd(), &header, 0)
+    };
+
+    if sent < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(sent as usize)
+    }
+}
@@ -0,0 +1,286 @@
+use std::error::Error;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::{Entry, Facility, Severity, Target};
+
+// collapses runs of identical entries into the first one and a "last message
+// repeated N times" entry, like the classic syslogd
+pub struct DedupTarget<T: Target> {
+    shared: Arc<Shared<T>>,
+    // reports a long run once the flush interval passes, even if nothing
+    // else is logged
+    timer: Option<(Sender<()>, JoinHandle<()>)>,
+}
+
+pub struct DedupTargetBuilder<T: Target> {
+    inner: T,
+    flush_interval: Duration,
+}
+
+struct Shared<T: Target> {
+    // always locked after the state, when both are needed
+    inner: Mutex<T>,
+    flush_interval: Duration,
+    state: Mutex<DedupState>,
+}
+
+struct DedupState {
+    last: Option<EntryKey>,
+    repeated: u64,
+    // when the first repeat not yet reported was logged
+    repeating_since: Option<Instant>,
+}
+
+// the parts of an entry compared to decide whether it is a repeat
+#[derive(PartialEq, Eq)]
+struct EntryKey {
+    prival: i32,
+    app_name: String,
+    msgid: String,
+    message: String,
+}
+
+impl<T: Target + Send + 'static> DedupTarget<T> {
+    pub fn new(inner: T) -> Result<Self, Box<dyn Error>> {
+        DedupTarget::builder(inner).build()
+    }
+
+    pub fn builder(inner: T) -> DedupTargetBuilder<T> {
+        DedupTargetBuilder {
+            inner,
+            flush_interval: Duration::from_secs(30),
+        }
+    }
+}
+
+impl<T: Target> DedupTarget<T> {
+    pub fn inner(&self) -> MutexGuard<'_, T> {
+        self.shared.inner.lock().unwrap()
+    }
+
+    // repeats of the last entry that haven't been reported yet
+    pub fn repeated(&self) -> u64 {
+        self.shared.state.lock().unwrap().repeated
+    }
+
+    // reports any repeats held back so far, without ending the run, so that
+    // later repeats are still collapsed
+    pub fn flush(&self) -> Result<(), Box<dyn Error>> {
+        let mut state = self.shared.state.lock().unwrap();
+        self.shared.report_repeats(&mut state)
+    }
+}
+
+impl<T: Target> Shared<T> {
+    fn report_repeats(&self, state: &mut DedupState) -> Result<(), Box<dyn Error>> {
+        let last = match &state.last {
+            Some(last) if state.repeated > 0 => last,
+            _ => return Ok(()),
+        };
+
+        let message = format!("last message repeated {} times", state.repeated);
+        state.repeated = 0;
+        state.repeating_since = None;
+
+        let summary = Entry::new(
+            Facility::User,
+            Severity::Notice,
+            &last.app_name,
+            &last.msgid,
+            &message,
+        )?;
+        summary.set_prival(last.prival)?;
+        self.inner.lock().unwrap().log(&summary)?;
+
+        Ok(())
+    }
+
+    // how long until the repeats held back are due to be reported
+    fn until_due(&self, state: &DedupState) -> Option<Duration> {
+        state
+            .repeating_since
+            .map(|since| (since + self.flush_interval).saturating_duration_since(Instant::now()))
+    }
+}
+
+impl<T: Target + Send + 'static> DedupTargetBuilder<T> {
+    // how long repeats are held back before being reported, even if the run
+    // hasn't ended yet
+    pub fn flush_interval(mut self, flush_interval: Duration) -> Self {
+        self.flush_interval = flush_interval;
+        self
+    }
+
+    pub fn build(self) -> Result<DedupTarget<T>, Box<dyn Error>> {
+        let shared = Arc::new(Shared {
+            inner: Mutex::new(self.inner),
+            flush_interval: self.flush_interval,
+            state: Mutex::new(DedupState {
+                last: None,
+                repeated: 0,
+                repeating_since: None,
+            }),
+        });
+
+        // with no interval every repeat is reported as it is logged
+        let timer = if self.flush_interval.is_zero() {
+            None
+        } else {
+            Some(spawn_timer(&shared)?)
+        };
+
+        Ok(DedupTarget { shared, timer })
+    }
+}
+
+impl<T: Target> Target for DedupTarget<T> {
+    // repeats are not written, and so return a length of zero
+    fn log(&self, entry: &Entry) -> Result<usize, Box<dyn Error>> {
+        let key = EntryKey {
+            prival: entry.get_prival()?,
+            app_name: entry.get_app_name()?,
+            msgid: entry.get_msgid()?,
+            message: entry.get_message()?,
+        };
+        let shared = &self.shared;
+        let mut state = shared.state.lock().unwrap();
+
+        if state.last.as_ref() == Some(&key) {
+            state.repeating_since.get_or_insert_with(Instant::now);
+            state.repeated += 1;
+
+            if shared.until_due(&state) == Some(Duration::ZERO) {
+                shared.report_repeats(&mut state)?;
+            }
+
+            return Ok(0);
+        }
+
+        shared.report_repeats(&mut state)?;
+        state.last = Some(key);
+
+        shared.inner.lock().unwrap().log(entry)
+    }
+}
+
+impl<T: Target> Drop for DedupTarget<T> {
+    fn drop(&mut self) {
+        if let Some((sender, timer)) = self.timer.take() {
+            drop(sender);
+            let _ = timer.join();
+        }
+
+        let _ = self.flush();
+    }
+}
+
+// sleeps until the repeats held back are due, or for a whole interval if
+// there are none, so a run is reported on time however long it goes quiet
+fn spawn_timer<T: Target + Send + 'static>(
+    shared: &Arc<Shared<T>>,
+) -> Result<(Sender<()>, JoinHandle<()>), Box<dyn Error>> {
+    let (sender, receiver) = mpsc::channel();
+    let shared = Arc::clone(shared);
+
+    let timer = thread::Builder::new()
+        .name(String::from("stumpless-dedup"))
+        .spawn(move || loop {
+            let wait = {
+                let state = shared.state.lock().unwrap();
+                shared.until_due(&state).unwrap_or(shared.flush_interval)
+            };
+
+            if receiver.recv_timeout(wait) != Err(RecvTimeoutError::Timeout) {
+                break;
+            }
+
+            let mut state = shared.state.lock().unwrap();
+            if shared.until_due(&state) == Some(Duration::ZERO) {
+                // a failed report is tried again with the next one
+                let _ = shared.report_repeats(&mut state);
+            }
+        })?;
+
+    Ok((sender, timer))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct Recording(Arc<Mutex<Vec<String>>>);
+
+    impl Target for Recording {
+        fn log(&self, entry: &Entry) -> Result<usize, Box<dyn Error>> {
+            let message = entry.get_message()?;
+            let length = message.len();
+            self.0.lock().unwrap().push(message);
+            Ok(length)
+        }
+    }
+
+    fn log(target: &DedupTarget<Recording>, message: &str) {
+        let entry = Entry::new(Facility::User, Severity::Info, "app", "msgid", message).unwrap();
+        target.log(&entry).unwrap();
+    }
+
+    #[test]
+    fn collapses_a_run_once_it_ends() {
+        let recording = Recording::default();
+        let target = DedupTarget::new(recording.clone()).unwrap();
+
+        for _ in 0..4 {
+            log(&target, "same");
+        }
+        assert_eq!(target.repeated(), 3);
+        log(&target, "different");
+
+        assert_eq!(
+            *recording.0.lock().unwrap(),
+            ["same", "last message repeated 3 times", "different"]
+        );
+    }
+
+    #[test]
+    fn reports_a_quiet_run_after_the_flush_interval() {
+        let recording = Recording::default();
+        let target = DedupTarget::builder(recording.clone())
+            .flush_interval(Duration::from_millis(50))
+            .build()
+            .unwrap();
+
+        log(&target, "same");
+        log(&target, "same");
+        log(&target, "same");
+
+        // nothing else is logged, so only the timer can report the run
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while target.repeated() > 0 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        assert_eq!(
+            *recording.0.lock().unwrap(),
+            ["same", "last message repeated 2 times"]
+        );
+    }
+
+    #[test]
+    fn reports_held_back_repeats_when_dropped() {
+        let recording = Recording::default();
+        let target = DedupTarget::new(recording.clone()).unwrap();
+
+        log(&target, "same");
+        log(&target, "same");
+        drop(target);
+
+        assert_eq!(
+            *recording.0.lock().unwrap(),
+            ["same", "last message repeated 1 times"]
+        );
+    }
+}
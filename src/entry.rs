@@ -1,16 +1,23 @@
-use stumpless_sys::{stumpless_add_entry, stumpless_entry, stumpless_new_entry_str, stumpless_set_entry_prival};
+use stumpless_sys::*;
 
 use crate::error::StumplessError;
 use crate::facility::Facility;
 use crate::severity::Severity;
 use crate::target::Target;
 use std::error::Error;
-use std::ffi::CString;
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
 
 pub struct Entry {
     entry: *mut stumpless_entry,
 }
 
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Element {
+    pub name: String,
+    pub params: Vec<(String, String)>,
+}
+
 impl Entry {
     pub fn new(
         facility: Facility,
@@ -39,6 +46,17 @@ impl Entry {
         }
     }
 
+    // copies the entry along with all of its elements and params
+    pub fn try_clone(&self) -> Result<Entry, Box<dyn Error>> {
+        let copy = unsafe { stumpless_copy_entry(self.entry) };
+
+        if copy.is_null() {
+            Err(Box::new(StumplessError))
+        } else {
+            Ok(Entry { entry: copy })
+        }
+    }
+
     pub fn set_prival(&self, prival: i32) -> Result<&Entry, Box<dyn Error>> {
         let set_result = unsafe { stumpless_set_entry_prival(self.entry, prival)};
 
@@ -48,14 +66,178 @@ impl Entry {
             Ok(self)
         }
     }
+
+    pub fn set_procid(&self, procid: &str) -> Result<&Entry, Box<dyn Error>> {
+        let c_procid = CString::new(procid)?;
+        let set_result = unsafe { stumpless_set_entry_procid(self.entry, c_procid.as_ptr()) };
+
+        if set_result.is_null() {
+            Err(Box::new(StumplessError))
+        } else {
+            Ok(self)
+        }
+    }
+
+    pub fn add_element(&self, name: &str) -> Result<&Entry, Box<dyn Error>> {
+        let c_name = CString::new(name)?;
+        let add_result = unsafe { stumpless_add_new_element(self.entry, c_name.as_ptr()) };
+
+        if add_result.is_null() {
+            Err(Box::new(StumplessError))
+        } else {
+            Ok(self)
+        }
+    }
+
+    pub fn add_param(&self, element: &str, name: &str, value: &str) -> Result<&Entry, Box<dyn Error>> {
+        let c_element = CString::new(element)?;
+        let c_name = CString::new(name)?;
+        let c_value = CString::new(value)?;
+        let add_result = unsafe {
+            stumpless_add_new_param_to_entry(
+                self.entry,
+                c_element.as_ptr(),
+                c_name.as_ptr(),
+                c_value.as_ptr(),
+            )
+        };
+
+        if add_result.is_null() {
+            Err(Box::new(StumplessError))
+        } else {
+            Ok(self)
+        }
+    }
+
+    // changes the value of the first param with the given name in the element
+    pub fn set_param(&self, element: &str, name: &str, value: &str) -> Result<&Entry, Box<dyn Error>> {
+        let c_element = CString::new(element)?;
+        let c_name = CString::new(name)?;
+        let c_value = CString::new(value)?;
+        let set_result = unsafe {
+            stumpless_set_entry_param_value_by_name(
+                self.entry,
+                c_element.as_ptr(),
+                c_name.as_ptr(),
+                c_value.as_ptr(),
+            )
+        };
+
+        if set_result.is_null() {
+            Err(Box::new(StumplessError))
+        } else {
+            Ok(self)
+        }
+    }
+
+    pub fn get_prival(&self) -> Result<i32, Box<dyn Error>> {
+        let prival = unsafe { stumpless_get_entry_prival(self.entry) };
+
+        if prival < 0 {
+            Err(Box::new(StumplessError))
+        } else {
+            Ok(prival)
+        }
+    }
+
+    pub fn get_facility(&self) -> Result<Facility, Box<dyn Error>> {
+        Ok(Facility::try_from(self.get_prival()? & !0x07)?)
+    }
+
+    pub fn get_severity(&self) -> Result<Severity, Box<dyn Error>> {
+        Ok(Severity::try_from(self.get_prival()? & 0x07)?)
+    }
+
+    pub fn get_app_name(&self) -> Result<String, Box<dyn Error>> {
+        take_c_string(unsafe { stumpless_get_entry_app_name(self.entry) })
+    }
+
+    pub fn get_msgid(&self) -> Result<String, Box<dyn Error>> {
+        take_c_string(unsafe { stumpless_get_entry_msgid(self.entry) })
+    }
+
+    pub fn get_message(&self) -> Result<String, Box<dyn Error>> {
+        let message = unsafe { stumpless_get_entry_message(self.entry) };
+
+        // entries without a message hand back NULL rather than an empty string
+        if message.is_null() {
+            Ok(String::new())
+        } else {
+            take_c_string(message)
+        }
+    }
+
+    // None means the entry is logged with the id of the current process
+    pub fn get_procid(&self) -> Result<Option<String>, Box<dyn Error>> {
+        let procid = unsafe { stumpless_get_entry_procid(self.entry) };
+
+        if procid.is_null() {
+            Ok(None)
+        } else {
+            take_c_string(procid).map(Some)
+        }
+    }
+
+    pub fn get_elements(&self) -> Result<Vec<Element>, Box<dyn Error>> {
+        let element_count = unsafe { stumpless_get_element_count(self.entry) };
+        let mut elements = Vec::with_capacity(element_count);
+
+        for i in 0..element_count {
+            let element = unsafe { stumpless_get_element_by_index(self.entry, i) };
+            if element.is_null() {
+                return Err(Box::new(StumplessError));
+            }
+
+            let name = take_c_string(unsafe { stumpless_get_element_name(element) })?;
+            let param_count = unsafe { stumpless_get_param_count(element) };
+            let mut params = Vec::with_capacity(param_count);
+
+            for j in 0..param_count {
+                let param = unsafe { stumpless_get_param_by_index(element, j) };
+                if param.is_null() {
+                    return Err(Box::new(StumplessError));
+                }
+
+                let param_name = take_c_string(unsafe { stumpless_get_param_name(param) })?;
+                let param_value = take_c_string(unsafe { stumpless_get_param_value(param) })?;
+                params.push((param_name, param_value));
+            }
+
+            elements.push(Element { name, params });
+        }
+
+        Ok(elements)
+    }
+
+    pub(crate) fn get_pointer(&self) -> *mut stumpless_entry {
+        self.entry
+    }
 }
 
-pub fn add_entry(target: &impl Target, entry: &Entry) -> Result<u32, Box<dyn Error>> {
-    let add_result = unsafe { stumpless_add_entry(target.get_pointer(), entry.entry) };
+// stumpless entries are thread safe
+unsafe impl Send for Entry {}
+unsafe impl Sync for Entry {}
+
+impl Drop for Entry {
+    fn drop(&mut self) {
+        unsafe {
+            stumpless_destroy_entry_and_contents(self.entry);
+        }
+    }
+}
 
-    if add_result >= 0 {
-        Ok(add_result.try_into().unwrap())
-    } else {
-        Err(Box::new(StumplessError))
+// strings returned by the stumpless getters are copies owned by the caller
+fn take_c_string(c_string: *const c_char) -> Result<String, Box<dyn Error>> {
+    if c_string.is_null() {
+        return Err(Box::new(StumplessError));
     }
+
+    let string = unsafe { CStr::from_ptr(c_string) }.to_string_lossy().into_owned();
+    unsafe { libc::free(c_string as *mut libc::c_void) };
+
+    Ok(string)
+}
+
+pub fn add_entry(target: &(impl Target + ?Sized), entry: &Entry) -> Result<usize, Box<dyn Error>> {
+    target.log(entry)
 }
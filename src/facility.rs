@@ -1,5 +1,8 @@
 use stumpless_sys::*;
 
+use crate::StumplessError;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Facility {
     Kernel = stumpless_facility_STUMPLESS_FACILITY_KERN as isize,
     User = stumpless_facility_STUMPLESS_FACILITY_USER as isize,
@@ -26,3 +29,37 @@ pub enum Facility {
     Local6 = stumpless_facility_STUMPLESS_FACILITY_LOCAL6 as isize,
     Local7 = stumpless_facility_STUMPLESS_FACILITY_LOCAL7 as isize,
 }
+
+impl TryFrom<i32> for Facility {
+    type Error = StumplessError;
+
+    fn try_from(value: i32) -> Result<Self, StumplessError> {
+        match value {
+            value if value == Facility::Kernel as i32 => Ok(Facility::Kernel),
+            value if value == Facility::User as i32 => Ok(Facility::User),
+            value if value == Facility::Mail as i32 => Ok(Facility::Mail),
+            value if value == Facility::Daemon as i32 => Ok(Facility::Daemon),
+            value if value == Facility::Auth as i32 => Ok(Facility::Auth),
+            value if value == Facility::Syslog as i32 => Ok(Facility::Syslog),
+            value if value == Facility::Lpr as i32 => Ok(Facility::Lpr),
+            value if value == Facility::News as i32 => Ok(Facility::News),
+            value if value == Facility::Uucp as i32 => Ok(Facility::Uucp),
+            value if value == Facility::Cron as i32 => Ok(Facility::Cron),
+            value if value == Facility::Auth2 as i32 => Ok(Facility::Auth2),
+            value if value == Facility::FTP as i32 => Ok(Facility::FTP),
+            value if value == Facility::NTP as i32 => Ok(Facility::NTP),
+            value if value == Facility::Audit as i32 => Ok(Facility::Audit),
+            value if value == Facility::Alert as i32 => Ok(Facility::Alert),
+            value if value == Facility::Cron2 as i32 => Ok(Facility::Cron2),
+            value if value == Facility::Local0 as i32 => Ok(Facility::Local0),
+            value if value == Facility::Local1 as i32 => Ok(Facility::Local1),
+            value if value == Facility::Local2 as i32 => Ok(Facility::Local2),
+            value if value == Facility::Local3 as i32 => Ok(Facility::Local3),
+            value if value == Facility::Local4 as i32 => Ok(Facility::Local4),
+            value if value == Facility::Local5 as i32 => Ok(Facility::Local5),
+            value if value == Facility::Local6 as i32 => Ok(Facility::Local6),
+            value if value == Facility::Local7 as i32 => Ok(Facility::Local7),
+            _ => Err(StumplessError),
+        }
+    }
+}
@@ -0,0 +1,246 @@
+use std::error::Error;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::{Entry, StumplessError, Target};
+
+// sends each entry to the first target in the list that accepts it, for
+// example a collector, then a local socket, then a file
+pub struct FailoverTarget {
+    targets: Vec<Box<dyn Target + Send>>,
+    retry_interval: Duration,
+    state: Mutex<FailoverState>,
+}
+
+pub struct FailoverTargetBuilder {
+    targets: Vec<Box<dyn Target + Send>>,
+    retry_interval: Duration,
+}
+
+struct FailoverState {
+    active: usize,
+    // when to next try the primary again, if it isn't the active target
+    next_probe: Option<Instant>,
+}
+
+impl FailoverTarget {
+    pub fn new(targets: Vec<Box<dyn Target + Send>>) -> Self {
+        FailoverTarget::builder().targets(targets).build()
+    }
+
+    pub fn builder() -> FailoverTargetBuilder {
+        FailoverTargetBuilder {
+            targets: Vec::new(),
+            retry_interval: Duration::from_secs(30),
+        }
+    }
+
+    pub fn targets(&self) -> &[Box<dyn Target + Send>] {
+        &self.targets
+    }
+
+    // the index of the target entries are currently sent to
+    pub fn active(&self) -> usize {
+        self.state.lock().unwrap().active
+    }
+
+    pub fn active_target(&self) -> Option<&(dyn Target + Send)> {
+        self.targets.get(self.active()).map(|target| &**target)
+    }
+}
+
+impl FailoverTargetBuilder {
+    // targets are tried in the order they are added
+    pub fn target(mut self, target: impl Target + Send + 'static) -> Self {
+        self.targets.push(Box::new(target));
+        self
+    }
+
+    pub fn targets(mut self, targets: Vec<Box<dyn Target + Send>>) -> Self {
+        self.targets.extend(targets);
+        self
+    }
+
+    // how long to wait after the primary fails before trying it again
+    pub fn retry_interval(mut self, retry_interval: Duration) -> Self {
+        self.retry_interval = retry_interval;
+        self
+    }
+
+    pub fn build(self) -> FailoverTarget {
+        FailoverTarget {
+            targets: self.targets,
+            retry_interval: self.retry_interval,
+            state: Mutex::new(FailoverState {
+                active: 0,
+                next_probe: None,
+            }),
+        }
+    }
+}
+
+impl Target for FailoverTarget {
+    fn log(&self, entry: &Entry) -> Result<usize, Box<dyn Error>> {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+
+        let start = match state.next_probe {
+            Some(next_probe) if next_probe <= now => 0,
+            _ => state.active,
+        };
+
+        // targets before the starting one failed recently, so they are only
+        // tried once everything after it has failed as well
+        let mut last_error: Box<dyn Error> = Box::new(StumplessError);
+        for i in (start..self.targets.len()).chain(0..start) {
+            match self.targets[i].log(entry) {
+                Ok(length) => {
+                    state.next_probe = if i == 0 {
+                        None
+                    } else if start == 0 {
+                        Some(now + self.retry_interval)
+                    } else {
+                        state.next_probe.or(Some(now + self.retry_interval))
+                    };
+                    state.active = i;
+
+                    return Ok(length);
+                }
+                Err(error) => last_error = error,
+            }
+        }
+
+        Err(last_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Facility, Severity};
+    use std::io;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    #[derive(Clone)]
+    struct Stub {
+        name: &'static str,
+        up: Arc<AtomicBool>,
+        messages: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Stub {
+        fn new(name: &'static str, up: bool) -> Self {
+            Stub {
+                name,
+                up: Arc::new(AtomicBool::new(up)),
+                messages: Arc::default(),
+            }
+        }
+
+        fn messages(&self) -> Vec<String> {
+            self.messages.lock().unwrap().clone()
+        }
+    }
+
+    impl Target for Stub {
+        fn log(&self, entry: &Entry) -> Result<usize, Box<dyn Error>> {
+            if !self.up.load(Ordering::SeqCst) {
+                return Err(Box::new(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!("{} is down", self.name),
+                )));
+            }
+
+            let message = entry.get_message()?;
+            let length = message.len();
+            self.messages.lock().unwrap().push(message);
+            Ok(length)
+        }
+    }
+
+    fn log(target: &FailoverTarget, message: &str) -> Result<usize, Box<dyn Error>> {
+        let entry = Entry::new(Facility::User, Severity::Info, "app", "msgid", message).unwrap();
+        target.log(&entry)
+    }
+
+    #[test]
+    fn falls_back_when_the_primary_fails() {
+        let primary = Stub::new("primary", false);
+        let secondary = Stub::new("secondary", true);
+        let failover = FailoverTarget::builder()
+            .target(primary.clone())
+            .target(secondary.clone())
+            .build();
+
+        assert_eq!(log(&failover, "first").unwrap(), 5);
+        assert_eq!(failover.active(), 1);
+        assert!(primary.messages().is_empty());
+        assert_eq!(secondary.messages(), ["first"]);
+    }
+
+    #[test]
+    fn returns_to_the_primary_after_the_retry_interval() {
+        let primary = Stub::new("primary", false);
+        let secondary = Stub::new("secondary", true);
+        let failover = FailoverTarget::builder()
+            .target(primary.clone())
+            .target(secondary.clone())
+            .retry_interval(Duration::from_millis(50))
+            .build();
+
+        log(&failover, "first").unwrap();
+        primary.up.store(true, Ordering::SeqCst);
+
+        // the primary is left alone until the interval passes
+        log(&failover, "second").unwrap();
+        assert_eq!(failover.active(), 1);
+
+        std::thread::sleep(Duration::from_millis(100));
+        log(&failover, "third").unwrap();
+        log(&failover, "fourth").unwrap();
+
+        assert_eq!(failover.active(), 0);
+        assert_eq!(primary.messages(), ["third", "fourth"]);
+        assert_eq!(secondary.messages(), ["first", "second"]);
+    }
+
+    #[test]
+    fn tries_earlier_targets_once_later_ones_fail() {
+        let primary = Stub::new("primary", false);
+        let secondary = Stub::new("secondary", true);
+        let failover = FailoverTarget::builder()
+            .target(primary.clone())
+            .target(secondary.clone())
+            .retry_interval(Duration::from_secs(60))
+            .build();
+
+        log(&failover, "first").unwrap();
+        primary.up.store(true, Ordering::SeqCst);
+        secondary.up.store(false, Ordering::SeqCst);
+        log(&failover, "second").unwrap();
+
+        assert_eq!(failover.active(), 0);
+        assert_eq!(primary.messages(), ["second"]);
+    }
+
+    #[test]
+    fn fails_with_the_last_error_when_every_target_fails() {
+        let failover = FailoverTarget::builder()
+            .target(Stub::new("primary", false))
+            .target(Stub::new("secondary", false))
+            .build();
+
+        let error = log(&failover, "lost").unwrap_err();
+        assert_eq!(error.to_string(), "secondary is down");
+        assert_eq!(failover.active(), 0);
+    }
+
+    #[test]
+    fn fails_without_any_targets() {
+        let failover = FailoverTarget::new(Vec::new());
+
+        assert!(log(&failover, "lost").is_err());
+        assert!(failover.active_target().is_none());
+    }
+}
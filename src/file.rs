@@ -2,39 +2,632 @@ use stumpless_sys::*;
 
 use std::error::Error;
 use std::ffi::CString;
+use std::fs::{self, File, Metadata, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
-use crate::StumplessError;
-use crate::Target;
+use crate::format::format_entry;
+use crate::rotating_file::{RotatingFileTarget, RotationInterval, RotationNaming, RotationPolicy};
+use crate::target::{add_entry_to_pointer, add_message_to_pointer, message_entry, TargetPointer};
+use crate::{Entry, Format, StumplessError, Target};
+
+// bumped by the SIGHUP handler, file targets reopen when it changes
+static REOPEN_GENERATION: AtomicUsize = AtomicUsize::new(0);
+
+// how often the file is checked for rotation by another process, so that a
+// busy target doesn't stat the file on every write
+const ROTATION_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyncPolicy {
+    Never,
+    EveryEntry,
+    Interval(Duration),
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct FileOptions {
+    pub mode: Option<u32>,
+    pub owner: Option<u32>,
+    pub group: Option<u32>,
+    pub create_parents: bool,
+    pub sync: SyncPolicy,
+    pub format: Format,
+}
+
+impl Default for FileOptions {
+    fn default() -> Self {
+        FileOptions {
+            mode: None,
+            owner: None,
+            group: None,
+            create_parents: false,
+            sync: SyncPolicy::Never,
+            format: Format::Rfc5424,
+        }
+    }
+}
 
 pub struct FileTarget {
-    target: *mut stumpless_target,
+    filename: String,
+    options: FileOptions,
+    state: Arc<Mutex<FileState>>,
+    // syncs on an interval until the sender is dropped
+    syncer: Option<(Sender<()>, JoinHandle<()>)>,
+}
+
+struct FileState {
+    // only opened when stumpless writes the entries
+    target: Option<TargetPointer>,
+    // entries are written through this handle instead when they need to be
+    // synced, as the stdio stream stumpless writes through can't be flushed
+    // from here, or when they are in a format stumpless doesn't support
+    file: File,
+    identity: Option<(u64, u64)>,
+    length: u64,
+    next_check: Instant,
+    generation: usize,
+    // whether anything was written since the last sync
+    dirty: bool,
+}
+
+pub struct FileTargetBuilder {
+    filename: String,
+    options: FileOptions,
+    rotation: RotationPolicy,
 }
 
 impl FileTarget {
     pub fn new(filename: &str) -> Result<Self, Box<dyn Error>> {
+        FileTarget::open(filename, FileOptions::default())
+    }
+
+    pub(crate) fn open(filename: &str, options: FileOptions) -> Result<Self, Box<dyn Error>> {
+        let state = Arc::new(Mutex::new(FileState::open(filename, &options)?));
+
+        let syncer = match options.sync {
+            SyncPolicy::Interval(interval) => Some(spawn_syncer(&state, interval)?),
+            _ => None,
+        };
+
+        Ok(FileTarget {
+            filename: filename.to_string(),
+            options,
+            state,
+            syncer,
+        })
+    }
+
+    pub fn reopen(&self) -> Result<(), Box<dyn Error>> {
+        let mut state = self.state.lock().unwrap();
+
+        // the old file stays open if the new one can't be opened
+        let reopened = FileState::open(&self.filename, &self.options)?;
+        state.sync()?;
+        *state = reopened;
+        Ok(())
+    }
+
+    // catches both create style rotation, where the file is moved away and a
+    // new one created, and copytruncate style rotation
+    fn was_rotated(&self, state: &mut FileState) -> bool {
+        let now = Instant::now();
+        if now < state.next_check {
+            return false;
+        }
+        state.next_check = now + ROTATION_CHECK_INTERVAL;
+
+        match fs::metadata(&self.filename) {
+            Ok(metadata) => {
+                let rotated =
+                    file_identity(&metadata) != state.identity || metadata.len() < state.length;
+                state.length = metadata.len();
+                rotated
+            }
+            Err(_) => true,
+        }
+    }
+
+    // a SIGHUP takes effect on the next write, while rotation by another
+    // process is only noticed once the next check is due
+    fn reopen_if_rotated(&self, state: &mut FileState) -> Result<(), Box<dyn Error>> {
+        if state.generation != REOPEN_GENERATION.load(Ordering::SeqCst) || self.was_rotated(state) {
+            let reopened = FileState::open(&self.filename, &self.options)?;
+            state.sync()?;
+            *state = reopened;
+        }
+
+        Ok(())
+    }
+
+    fn write_entry(&self, state: &mut FileState, entry: &Entry) -> Result<usize, Box<dyn Error>> {
+        let add_result = match state.target {
+            Some(target) => add_entry_to_pointer(target.0, entry)?,
+            None => state.write_line(&format_entry(self.options.format, entry)?)?,
+        };
+
+        // interval syncs are left to the syncer thread
+        if self.options.sync == SyncPolicy::EveryEntry {
+            state.sync()?;
+        }
+
+        Ok(add_result)
+    }
+
+    pub fn builder(filename: &str) -> FileTargetBuilder {
+        FileTargetBuilder {
+            filename: filename.to_string(),
+            options: FileOptions::default(),
+            rotation: RotationPolicy {
+                max_size: None,
+                interval: None,
+                keep: 5,
+                naming: RotationNaming::Index,
+                compress: false,
+            },
+        }
+    }
+}
+
+impl FileTargetBuilder {
+    #[cfg(unix)]
+    pub fn mode(mut self, mode: u32) -> Self {
+        self.options.mode = Some(mode);
+        self
+    }
+
+    #[cfg(unix)]
+    pub fn owner(mut self, uid: u32) -> Self {
+        self.options.owner = Some(uid);
+        self
+    }
+
+    #[cfg(unix)]
+    pub fn group(mut self, gid: u32) -> Self {
+        self.options.group = Some(gid);
+        self
+    }
+
+    pub fn create_parents(mut self, create_parents: bool) -> Self {
+        self.options.create_parents = create_parents;
+        self
+    }
+
+    pub fn sync(mut self, sync: SyncPolicy) -> Self {
+        self.options.sync = sync;
+        self
+    }
+
+    pub fn format(mut self, format: Format) -> Self {
+        self.options.format = format;
+        self
+    }
+
+    pub fn max_size(mut self, bytes: u64) -> Self {
+        self.rotation.max_size = Some(bytes);
+        self
+    }
+
+    pub fn rotate(mut self, interval: RotationInterval) -> Self {
+        self.rotation.interval = Some(interval);
+        self
+    }
+
+    pub fn keep(mut self, count: usize) -> Self {
+        self.rotation.keep = count;
+        self
+    }
+
+    pub fn naming(mut self, naming: RotationNaming) -> Self {
+        self.rotation.naming = naming;
+        self
+    }
+
+    #[cfg(feature = "gzip")]
+    pub fn compress(mut self, compress: bool) -> Self {
+        self.rotation.compress = compress;
+        self
+    }
+
+    pub fn build(self) -> Result<FileTarget, Box<dyn Error>> {
+        FileTarget::open(&self.filename, self.options)
+    }
+
+    pub fn build_rotating(self) -> Result<RotatingFileTarget, Box<dyn Error>> {
+        RotatingFileTarget::open(&self.filename, self.options, self.rotation)
+    }
+}
+
+impl FileState {
+    fn open(filename: &str, options: &FileOptions) -> Result<Self, Box<dyn Error>> {
         let c_filename = CString::new(filename)?;
-        let file_target = unsafe { stumpless_open_file_target(c_filename.as_ptr()) };
+        let generation = REOPEN_GENERATION.load(Ordering::SeqCst);
+        let file = create_file(filename, options)?;
+
+        // stumpless opens files in append mode, so every write lands at the
+        // end of the file even if other processes write to it as well, and
+        // so does the handle opened above
+        let target = if options.format == Format::Rfc5424 && options.sync == SyncPolicy::Never {
+            let file_target = unsafe { stumpless_open_file_target(c_filename.as_ptr()) };
+
+            if file_target.is_null() {
+                return Err(Box::new(StumplessError));
+            }
 
-        if file_target.is_null() {
-            Err(Box::new(StumplessError))
+            Some(TargetPointer(file_target))
         } else {
-            Ok(FileTarget {
-                target: file_target,
-            })
+            None
+        };
+
+        let metadata = file.metadata()?;
+
+        Ok(FileState {
+            target,
+            file,
+            identity: file_identity(&metadata),
+            length: metadata.len(),
+            next_check: Instant::now() + ROTATION_CHECK_INTERVAL,
+            generation,
+            dirty: false,
+        })
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<usize> {
+        let line = format!("{}\n", line);
+        self.file.write_all(line.as_bytes())?;
+        self.dirty = true;
+
+        Ok(line.len())
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        if self.dirty {
+            self.file.sync_data()?;
+            self.dirty = false;
         }
+
+        Ok(())
     }
 }
 
 impl Target for FileTarget {
-    fn get_pointer(&self) -> *mut stumpless_target {
-        self.target
+    fn log(&self, entry: &Entry) -> Result<usize, Box<dyn Error>> {
+        let mut state = self.state.lock().unwrap();
+
+        self.reopen_if_rotated(&mut state)?;
+        self.write_entry(&mut state, entry)
+    }
+
+    fn log_message(&self, message: &str) -> Result<usize, Box<dyn Error>> {
+        let mut state = self.state.lock().unwrap();
+
+        self.reopen_if_rotated(&mut state)?;
+        match state.target {
+            Some(target) => add_message_to_pointer(target.0, message),
+            None => self.write_entry(&mut state, &message_entry(message)?),
+        }
     }
 }
 
 impl Drop for FileTarget {
     fn drop(&mut self) {
+        // the syncer syncs once more on its way out
+        if let Some((sender, syncer)) = self.syncer.take() {
+            drop(sender);
+            let _ = syncer.join();
+        }
+    }
+}
+
+impl Drop for FileState {
+    fn drop(&mut self) {
+        if let Some(target) = self.target {
+            unsafe {
+                stumpless_close_file_target(target.0);
+            }
+        }
+    }
+}
+
+fn spawn_syncer(
+    state: &Arc<Mutex<FileState>>,
+    interval: Duration,
+) -> io::Result<(Sender<()>, JoinHandle<()>)> {
+    let (sender, receiver) = mpsc::channel();
+    let state = Arc::clone(state);
+
+    let syncer = thread::Builder::new()
+        .name(String::from("stumpless-file-sync"))
+        .spawn(move || loop {
+            let stopping = receiver.recv_timeout(interval) == Err(RecvTimeoutError::Disconnected);
+
+            // a failed sync is tried again on the next tick
+            let _ = state.lock().unwrap().sync();
+
+            if stopping {
+                break;
+            }
+        })?;
+
+    Ok((sender, syncer))
+}
+
+fn create_file(filename: &str, options: &FileOptions) -> Result<File, Box<dyn Error>> {
+    if options.create_parents {
+        if let Some(parent) = Path::new(filename).parent() {
+            fs::create_dir_all(parent)?;
+        }
+    }
+
+    let mut open_options = OpenOptions::new();
+    open_options.append(true).create(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        use std::os::unix::io::AsRawFd;
+
+        if let Some(mode) = options.mode {
+            open_options.mode(mode);
+        }
+
+        let file = open_options.open(filename)?;
+
+        // the mode given to open is filtered through the umask
+        if let Some(mode) = options.mode {
+            file.set_permissions(fs::Permissions::from_mode(mode))?;
+        }
+
+        // an id of -1 leaves the owner or group as it is
+        if options.owner.is_some() || options.group.is_some() {
+            let chown_result = unsafe {
+                libc::fchown(
+                    file.as_raw_fd(),
+                    options.owner.unwrap_or(libc::uid_t::MAX),
+                    options.group.unwrap_or(libc::gid_t::MAX),
+                )
+            };
+
+            if chown_result != 0 {
+                return Err(Box::new(io::Error::last_os_error()));
+            }
+        }
+
+        Ok(file)
+    }
+
+    #[cfg(not(unix))]
+    Ok(open_options.open(filename)?)
+}
+
+#[cfg(unix)]
+fn file_identity(metadata: &Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_identity(_metadata: &Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+#[cfg(unix)]
+extern "C" fn handle_sighup(_signal: libc::c_int) {
+    REOPEN_GENERATION.fetch_add(1, Ordering::SeqCst);
+}
+
+// once installed, every file target reopens its file before the next write
+// that follows a SIGHUP
+#[cfg(unix)]
+pub fn reopen_file_targets_on_sighup() -> Result<(), Box<dyn Error>> {
+    let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+    action.sa_sigaction = handle_sighup as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    action.sa_flags = libc::SA_RESTART;
+
+    let action_result = unsafe {
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(libc::SIGHUP, &action, std::ptr::null_mut())
+    };
+
+    if action_result == 0 {
+        Ok(())
+    } else {
+        Err(Box::new(std::io::Error::last_os_error()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Facility, Severity};
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("stumpless-{}-{}.log", name, std::process::id()))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[test]
+    fn synced_entries_skip_stumpless() {
+        let path = temp_path("sync-every-entry");
+        let target = FileTarget::builder(&path)
+            .sync(SyncPolicy::EveryEntry)
+            .build()
+            .unwrap();
+        let entry = Entry::new(Facility::User, Severity::Info, "app", "msgid", "synced").unwrap();
+
+        target.log(&entry).unwrap();
+        target.log_message("plain").unwrap();
+        let contents = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let state = target.state.lock().unwrap();
+        assert!(state.target.is_none());
+        assert!(!state.dirty);
+        assert_eq!(contents.lines().count(), 2);
+        assert!(contents
+            .lines()
+            .next()
+            .unwrap()
+            .ends_with(" msgid - synced"));
+        assert!(contents.lines().nth(1).unwrap().ends_with(" plain"));
+    }
+
+    #[test]
+    fn interval_sync_happens_without_another_entry() {
+        let path = temp_path("sync-interval");
+        let target = FileTarget::builder(&path)
+            .sync(SyncPolicy::Interval(Duration::from_millis(10)))
+            .build()
+            .unwrap();
+
+        target.log_message("dirty").unwrap();
+        assert!(target.state.lock().unwrap().dirty);
+
+        thread::sleep(Duration::from_millis(200));
+        assert!(!target.state.lock().unwrap().dirty);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn dropping_stops_the_syncer() {
+        let path = temp_path("sync-drop");
+        let target = FileTarget::builder(&path)
+            .sync(SyncPolicy::Interval(Duration::from_secs(3600)))
+            .build()
+            .unwrap();
+        target.log_message("pending").unwrap();
+        let state = Arc::clone(&target.state);
+
+        drop(target);
+
+        assert!(!state.lock().unwrap().dirty);
+        assert_eq!(Arc::strong_count(&state), 1);
+        fs::remove_file(&path).unwrap();
+    }
+
+    // a SIGHUP makes every target reopen, which tests relying on the file not
+    // being reopened have to wait out
+    static SIGHUP: Mutex<()> = Mutex::new(());
+
+    fn check_now(target: &FileTarget) {
+        target.state.lock().unwrap().next_check = Instant::now();
+    }
+
+    #[test]
+    fn reopen_starts_a_new_file() {
+        let path = temp_path("reopen");
+        let moved = format!("{}.1", path);
+        let target = FileTarget::builder(&path)
+            .format(Format::JsonLines)
+            .build()
+            .unwrap();
+
+        target.log_message("before").unwrap();
+        fs::rename(&path, &moved).unwrap();
+        target.reopen().unwrap();
+        target.log_message("after").unwrap();
+
+        let current = fs::read_to_string(&path).unwrap();
+        let old = fs::read_to_string(&moved).unwrap();
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&moved).unwrap();
+
+        assert!(old.contains("before") && !old.contains("after"));
+        assert!(current.contains("after") && !current.contains("before"));
+    }
+
+    #[test]
+    fn follows_a_renamed_file() {
+        let path = temp_path("rotated-rename");
+        let moved = format!("{}.1", path);
+        let target = FileTarget::new(&path).unwrap();
+
+        target.log_message("before").unwrap();
+        fs::rename(&path, &moved).unwrap();
+        check_now(&target);
+        target.log_message("after").unwrap();
+
+        let current = fs::read_to_string(&path).unwrap();
+        let old = fs::read_to_string(&moved).unwrap();
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&moved).unwrap();
+
+        assert!(old.ends_with(" before\n"));
+        assert!(current.ends_with(" after\n"));
+        assert_eq!(current.lines().count(), 1);
+    }
+
+    #[test]
+    fn notices_a_truncated_file() {
+        let path = temp_path("rotated-truncate");
+        let target = FileTarget::new(&path).unwrap();
+
+        target.log_message("before").unwrap();
+        check_now(&target);
+        assert!(!target.was_rotated(&mut target.state.lock().unwrap()));
+
+        fs::OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(0)
+            .unwrap();
+        check_now(&target);
+        let rotated = target.was_rotated(&mut target.state.lock().unwrap());
+        fs::remove_file(&path).unwrap();
+
+        assert!(rotated);
+    }
+
+    #[test]
+    fn checks_for_rotation_at_most_once_an_interval() {
+        let _sighup = SIGHUP.lock().unwrap();
+        let path = temp_path("rotated-interval");
+        let moved = format!("{}.1", path);
+        let target = FileTarget::new(&path).unwrap();
+
+        target.log_message("before").unwrap();
+        fs::rename(&path, &moved).unwrap();
+        target.log_message("not checked yet").unwrap();
+
+        let old = fs::read_to_string(&moved).unwrap();
+        fs::remove_file(&moved).unwrap();
+
+        assert_eq!(old.lines().count(), 2);
+        assert!(!Path::new(&path).exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn reopens_after_sighup() {
+        let _sighup = SIGHUP.lock().unwrap();
+        let path = temp_path("sighup");
+        let moved = format!("{}.1", path);
+        let target = FileTarget::new(&path).unwrap();
+        reopen_file_targets_on_sighup().unwrap();
+
+        target.log_message("before").unwrap();
+        fs::rename(&path, &moved).unwrap();
         unsafe {
-            stumpless_close_file_target(self.target);
+            libc::raise(libc::SIGHUP);
         }
+        target.log_message("after").unwrap();
+
+        let current = fs::read_to_string(&path).unwrap();
+        let old = fs::read_to_string(&moved).unwrap();
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&moved).unwrap();
+
+        assert!(old.ends_with(" before\n"));
+        assert!(current.ends_with(" after\n"));
     }
 }
@@ -0,0 +1,331 @@
+use std::error::Error;
+use std::fmt::Write;
+
+use crate::timestamp::Timestamp;
+use crate::{Element, Entry};
+
+// indexed by facility code, using the names of the Facility variants where
+// syslog has no common keyword
+const FACILITY_NAMES: [&str; 24] = [
+    "kern", "user", "mail", "daemon", "auth", "syslog", "lpr", "news", "uucp", "cron", "authpriv",
+    "ftp", "ntp", "audit", "alert", "cron2", "local0", "local1", "local2", "local3", "local4",
+    "local5", "local6", "local7",
+];
+
+const SEVERITY_NAMES: [&str; 8] = [
+    "emerg", "alert", "crit", "err", "warning", "notice", "info", "debug",
+];
+
+// how targets that write entries themselves, rather than through stumpless,
+// format each one
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Rfc5424,
+    // a single line JSON object per entry
+    JsonLines,
+}
+
+pub(crate) fn format_entry(format: Format, entry: &Entry) -> Result<String, Box<dyn Error>> {
+    match format {
+        Format::Rfc5424 => rfc5424(entry),
+        Format::JsonLines => json_line(entry),
+    }
+}
+
+// formats entries the same way stumpless does, for targets that write to
+// their destination without going through the library
+pub(crate) fn rfc5424(entry: &Entry) -> Result<String, Box<dyn Error>> {
+    let mut message = format!(
+        "<{}>1 {} {} {} {} {} ",
+        entry.get_prival()?,
+        Timestamp::now().to_rfc3339(),
+        hostname(),
+        nil_if_empty(&entry.get_app_name()?),
+        procid(entry)?,
+        nil_if_empty(&entry.get_msgid()?),
+    );
+
+    let elements = entry.get_elements()?;
+    if elements.is_empty() {
+        message.push('-');
+    }
+
+    for element in elements {
+        write!(message, "[{}", element.name)?;
+        for (name, value) in element.params {
+            write!(message, " {}=\"{}\"", name, escape_param_value(&value))?;
+        }
+        message.push(']');
+    }
+
+    let entry_message = entry.get_message()?;
+    if !entry_message.is_empty() {
+        message.push(' ');
+        message.push_str(&entry_message);
+    }
+
+    Ok(message)
+}
+
+// structured data elements become objects holding their params, and the
+// nil values of RFC 5424 become nulls
+//
+// stumpless doesn't expose when an entry was created, so the timestamp is
+// the time the entry is formatted, as it is in the RFC 5424 format
+fn json_line(entry: &Entry) -> Result<String, Box<dyn Error>> {
+    let prival = entry.get_prival()?;
+    let (facility, severity) = ((prival / 8) as usize, (prival % 8) as usize);
+
+    let mut json = format!(
+        "{{\"timestamp\":{},\"facility\":{},\"facility_code\":{},\"severity\":{},\"severity_code\":{},\"hostname\":{},\"app_name\":{},\"procid\":{},\"msgid\":{},\"message\":{},\"structured_data\":",
+        json_string(&Timestamp::now().to_rfc3339()),
+        json_string(FACILITY_NAMES.get(facility).unwrap_or(&"")),
+        facility,
+        json_string(SEVERITY_NAMES[severity]),
+        severity,
+        json_string(&hostname()),
+        json_string_or_null(&entry.get_app_name()?),
+        json_string_or_null(&procid(entry)?),
+        json_string_or_null(&entry.get_msgid()?),
+        json_string(&entry.get_message()?),
+    );
+
+    json.push_str(&json_structured_data(entry.get_elements()?));
+    json.push('}');
+    Ok(json)
+}
+
+// a param name and each value it was given
+type MergedParam = (String, Vec<String>);
+
+// elements sharing a name are merged into a single object, as JSON objects
+// can't repeat a key, and so a param given more than once has an array of
+// all of its values in the order they were given
+fn json_structured_data(elements: Vec<Element>) -> String {
+    let mut merged: Vec<(String, Vec<MergedParam>)> = Vec::new();
+
+    for element in elements {
+        let index = match merged.iter().position(|(name, _)| *name == element.name) {
+            Some(index) => index,
+            None => {
+                merged.push((element.name, Vec::new()));
+                merged.len() - 1
+            }
+        };
+
+        let params = &mut merged[index].1;
+        for (name, value) in element.params {
+            match params
+                .iter_mut()
+                .find(|(param_name, _)| *param_name == name)
+            {
+                Some((_, values)) => values.push(value),
+                None => params.push((name, vec![value])),
+            }
+        }
+    }
+
+    let objects: Vec<String> = merged
+        .iter()
+        .map(|(name, params)| {
+            let params: Vec<String> = params
+                .iter()
+                .map(|(param_name, values)| {
+                    let value = match values.as_slice() {
+                        [value] => json_string(value),
+                        values => {
+                            let values: Vec<String> =
+                                values.iter().map(|value| json_string(value)).collect();
+                            format!("[{}]", values.join(","))
+                        }
+                    };
+
+                    format!("{}:{}", json_string(param_name), value)
+                })
+                .collect();
+
+            format!("{}:{{{}}}", json_string(name), params.join(","))
+        })
+        .collect();
+
+    format!("{{{}}}", objects.join(","))
+}
+
+pub(crate) fn procid(entry: &Entry) -> Result<String, Box<dyn Error>> {
+    Ok(entry
+        .get_procid()?
+        .unwrap_or_else(|| std::process::id().to_string()))
+}
+
+pub(crate) fn hostname() -> String {
+    #[cfg(unix)]
+    {
+        let mut buffer = [0u8; 256];
+        let result = unsafe { libc::gethostname(buffer.as_mut_ptr().cast(), buffer.len()) };
+
+        if result == 0 {
+            let length = buffer.iter().position(|&b| b == 0).unwrap_or(buffer.len());
+            if length > 0 {
+                return String::from_utf8_lossy(&buffer[..length]).into_owned();
+            }
+        }
+    }
+
+    #[cfg(windows)]
+    if let Ok(hostname) = std::env::var("COMPUTERNAME") {
+        return hostname;
+    }
+
+    String::from("-")
+}
+
+// quotes and escapes a string for use as a JSON value
+pub(crate) fn json_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c < ' ' => {
+                let _ = write!(quoted, "\\u{:04x}", c as u32);
+            }
+            c => quoted.push(c),
+        }
+    }
+
+    quoted.push('"');
+    quoted
+}
+
+fn json_string_or_null(value: &str) -> String {
+    if value.is_empty() || value == "-" {
+        String::from("null")
+    } else {
+        json_string(value)
+    }
+}
+
+fn nil_if_empty(value: &str) -> &str {
+    if value.is_empty() {
+        "-"
+    } else {
+        value
+    }
+}
+
+fn escape_param_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+
+    for c in value.chars() {
+        if c == '"' || c == '\\' || c == ']' {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Facility, Severity};
+
+    fn element(name: &str, params: &[(&str, &str)]) -> Element {
+        Element {
+            name: name.to_string(),
+            params: params
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+        }
+    }
+
+    // the timestamp is the only part that changes from one call to the next
+    fn without_timestamp(json: &str) -> String {
+        let start = json.find("\"timestamp\":\"").unwrap();
+        let end = start + json[start + 13..].find('"').unwrap() + 15;
+        format!("{}{}", &json[..start], &json[end..])
+    }
+
+    #[test]
+    fn formats_entries_as_a_single_json_object() {
+        let entry =
+            Entry::new(Facility::Local3, Severity::Warning, "app", "login", "hello").unwrap();
+        entry.set_procid("1234").unwrap();
+        entry.add_element("origin").unwrap();
+        entry.add_param("origin", "ip", "192.0.2.1").unwrap();
+
+        let json = json_line(&entry).unwrap();
+        let timestamp = &json[14..json[14..].find('"').unwrap() + 14];
+
+        assert!(json.starts_with("{\"timestamp\":\""));
+        assert!(timestamp.ends_with('Z'), "{}", timestamp);
+        assert!(!json.contains('\n'));
+        assert_eq!(
+            without_timestamp(&json),
+            format!(
+                "{{\"facility\":\"local3\",\"facility_code\":19,\"severity\":\"warning\",\"severity_code\":4,\"hostname\":{},\"app_name\":\"app\",\"procid\":\"1234\",\"msgid\":\"login\",\"message\":\"hello\",\"structured_data\":{{\"origin\":{{\"ip\":\"192.0.2.1\"}}}}}}",
+                json_string(&hostname())
+            )
+        );
+    }
+
+    #[test]
+    fn writes_nil_values_as_null() {
+        let entry = Entry::new(Facility::User, Severity::Info, "-", "-", "").unwrap();
+        entry.set_procid("-").unwrap();
+
+        let json = without_timestamp(&json_line(&entry).unwrap());
+        assert!(
+            json.ends_with(
+                "\"app_name\":null,\"procid\":null,\"msgid\":null,\"message\":\"\",\"structured_data\":{}}"
+            ),
+            "{}",
+            json
+        );
+    }
+
+    // as in the RFC 5424 and GELF formats
+    #[test]
+    fn writes_the_process_id_when_procid_is_unset() {
+        let entry = Entry::new(Facility::User, Severity::Info, "", "", "").unwrap();
+
+        let json = without_timestamp(&json_line(&entry).unwrap());
+        assert!(
+            json.ends_with(&format!(
+                "\"app_name\":null,\"procid\":\"{}\",\"msgid\":null,\"message\":\"\",\"structured_data\":{{}}}}",
+                std::process::id()
+            )),
+            "{}",
+            json
+        );
+    }
+
+    #[test]
+    fn escapes_strings() {
+        assert_eq!(
+            json_string("quote \" backslash \\ newline \n return \r tab \t bell \u{7} é"),
+            "\"quote \\\" backslash \\\\ newline \\n return \\r tab \\t bell \\u0007 é\""
+        );
+    }
+
+    #[test]
+    fn merges_elements_and_repeated_params() {
+        let json = json_structured_data(vec![
+            element("request", &[("id", "1"), ("tag", "a")]),
+            element("user", &[("name", "\"quoted\"")]),
+            element("request", &[("tag", "b"), ("tag", "c")]),
+        ]);
+
+        assert_eq!(
+            json,
+            "{\"request\":{\"id\":\"1\",\"tag\":[\"a\",\"b\",\"c\"]},\"user\":{\"name\":\"\\\"quoted\\\"\"}}"
+        );
+    }
+}
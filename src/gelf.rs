@@ -0,0 +1,490 @@
+use std::collections::hash_map::RandomState;
+use std::error::Error;
+use std::fmt::Write as _;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Write};
+use std::net::{TcpStream, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use flate2::write::{GzEncoder, ZlibEncoder};
+
+use crate::format::{hostname, json_string, procid};
+use crate::retry::{is_closed, RetryPolicy};
+use crate::server::{first_address, unspecified_address, with_default_port};
+use crate::{Entry, Target};
+
+// the port Graylog listens for GELF on by default
+pub const DEFAULT_GELF_PORT: u16 = 12201;
+
+// the most chunks a message can be split into
+const MAX_CHUNKS: usize = 128;
+
+// the magic bytes, message id, sequence number and sequence count
+const CHUNK_HEADER_SIZE: usize = 12;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GelfTransport {
+    // messages too large for a single datagram are sent in chunks
+    Udp,
+    // messages are terminated with a null byte
+    Tcp,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GelfCompression {
+    None,
+    Zlib,
+    Gzip,
+}
+
+// sends entries to Graylog and other GELF receivers as JSON
+pub struct GelfTarget {
+    server: String,
+    compression: GelfCompression,
+    chunk_size: usize,
+    retry: RetryPolicy,
+    connection: Connection,
+    next_message_id: AtomicU64,
+}
+
+enum Connection {
+    Udp(UdpSocket),
+    // empty once the connection is lost, until the next entry reconnects
+    Tcp(Mutex<Option<TcpStream>>),
+}
+
+pub struct GelfTargetBuilder {
+    server: String,
+    transport: GelfTransport,
+    compression: GelfCompression,
+    chunk_size: usize,
+    retry: RetryPolicy,
+}
+
+impl GelfTarget {
+    pub fn new(server: &str) -> Result<Self, Box<dyn Error>> {
+        GelfTarget::builder(server).build()
+    }
+
+    pub fn builder(server: &str) -> GelfTargetBuilder {
+        GelfTargetBuilder {
+            server: server.to_string(),
+            transport: GelfTransport::Udp,
+            compression: GelfCompression::None,
+            chunk_size: 1420,
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    pub fn server(&self) -> &str {
+        &self.server
+    }
+
+    pub fn transport(&self) -> GelfTransport {
+        match self.connection {
+            Connection::Udp(_) => GelfTransport::Udp,
+            Connection::Tcp(_) => GelfTransport::Tcp,
+        }
+    }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        self.retry
+    }
+
+    fn send(&self, payload: &[u8]) -> io::Result<usize> {
+        match &self.connection {
+            Connection::Udp(socket) => {
+                if payload.len() <= self.chunk_size {
+                    return socket.send(payload);
+                }
+
+                let chunk_data_size = self.chunk_size - CHUNK_HEADER_SIZE;
+                let count = (payload.len() + chunk_data_size - 1) / chunk_data_size;
+                if count > MAX_CHUNKS {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!(
+                            "GELF message needs {} chunks, over the limit of {}",
+                            count, MAX_CHUNKS
+                        ),
+                    ));
+                }
+
+                let message_id = self.next_message_id.fetch_add(1, Ordering::Relaxed);
+                let mut sent = 0;
+                for (sequence, data) in payload.chunks(chunk_data_size).enumerate() {
+                    let mut chunk = Vec::with_capacity(CHUNK_HEADER_SIZE + data.len());
+                    chunk.extend_from_slice(&[0x1e, 0x0f]);
+                    chunk.extend_from_slice(&message_id.to_be_bytes());
+                    chunk.push(sequence as u8);
+                    chunk.push(count as u8);
+                    chunk.extend_from_slice(data);
+
+                    sent += socket.send(&chunk)?;
+                }
+
+                Ok(sent)
+            }
+            Connection::Tcp(stream) => {
+                let mut framed = payload.to_vec();
+                framed.push(0);
+                let mut attempt = 0;
+
+                loop {
+                    {
+                        let mut stream = stream.lock().unwrap();
+
+                        let error = match self.write(&mut stream, &framed) {
+                            Ok(()) => return Ok(framed.len()),
+                            Err(error) => error,
+                        };
+
+                        if attempt >= self.retry.max_retries {
+                            return Err(error);
+                        }
+                    }
+
+                    // unlocked while waiting, so other threads can still
+                    // get their entry through
+                    thread::sleep(self.retry.backoff(attempt));
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    fn write(&self, stream: &mut Option<TcpStream>, framed: &[u8]) -> io::Result<()> {
+        if stream.as_ref().map_or(false, is_closed) {
+            *stream = None;
+        }
+
+        if stream.is_none() {
+            *stream = Some(connect(&self.server, &self.retry)?);
+        }
+
+        let result = stream.as_mut().unwrap().write_all(framed);
+        if result.is_err() {
+            *stream = None;
+        }
+
+        result
+    }
+}
+
+impl GelfTargetBuilder {
+    pub fn transport(mut self, transport: GelfTransport) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    // only supported over UDP, as GELF receivers expect plain JSON over TCP
+    pub fn compression(mut self, compression: GelfCompression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    // the largest datagram to send over UDP, header included, which defaults
+    // to one that fits in a typical ethernet frame
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    // only used over TCP, as there is no way to tell a datagram was lost
+    pub fn retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn build(self) -> Result<GelfTarget, Box<dyn Error>> {
+        let server = with_default_port(&self.server, DEFAULT_GELF_PORT);
+
+        let connection = match self.transport {
+            GelfTransport::Udp => {
+                if self.chunk_size <= CHUNK_HEADER_SIZE {
+                    return Err(invalid_input(
+                        "the GELF chunk size must be larger than the chunk header",
+                    ));
+                }
+
+                let address = first_address(server.to_socket_addrs()?, &server)?;
+                let socket = UdpSocket::bind(unspecified_address(&address))?;
+                socket.connect(address)?;
+                Connection::Udp(socket)
+            }
+            GelfTransport::Tcp => {
+                if self.compression != GelfCompression::None {
+                    return Err(invalid_input("GELF over TCP can't be compressed"));
+                }
+
+                Connection::Tcp(Mutex::new(Some(connect(&server, &self.retry)?)))
+            }
+        };
+
+        Ok(GelfTarget {
+            server,
+            compression: self.compression,
+            chunk_size: self.chunk_size,
+            retry: self.retry,
+            connection,
+            next_message_id: AtomicU64::new(RandomState::new().build_hasher().finish()),
+        })
+    }
+}
+
+impl Target for GelfTarget {
+    fn log(&self, entry: &Entry) -> Result<usize, Box<dyn Error>> {
+        let message = gelf_message(entry)?;
+
+        let payload = match self.compression {
+            GelfCompression::None => message.into_bytes(),
+            GelfCompression::Zlib => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(message.as_bytes())?;
+                encoder.finish()?
+            }
+            GelfCompression::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(message.as_bytes())?;
+                encoder.finish()?
+            }
+        };
+
+        Ok(self.send(&payload)?)
+    }
+}
+
+// the GELF 1.1 payload, with the app name, msgid, procid and structured data
+// params as additional fields
+//
+// the timestamp is taken when the payload is built rather than when the entry
+// was created, which stumpless has no way to hand back
+fn gelf_message(entry: &Entry) -> Result<String, Box<dyn Error>> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+    let short_message = match entry.get_message()? {
+        message if message.is_empty() => String::from("-"),
+        message => message,
+    };
+
+    let mut json = format!(
+        "{{\"version\":\"1.1\",\"host\":{},\"short_message\":{},\"timestamp\":{}.{:06},\"level\":{}",
+        json_string(&hostname()),
+        json_string(&short_message),
+        now.as_secs(),
+        now.subsec_micros(),
+        entry.get_severity()? as i32,
+    );
+
+    let app_name = entry.get_app_name()?;
+    if !app_name.is_empty() {
+        write!(json, ",\"_app\":{}", json_string(&app_name))?;
+    }
+
+    let msgid = entry.get_msgid()?;
+    if !msgid.is_empty() {
+        write!(json, ",\"_msgid\":{}", json_string(&msgid))?;
+    }
+
+    write!(json, ",\"_procid\":{}", json_string(&procid(entry)?))?;
+
+    for element in entry.get_elements()? {
+        for (name, value) in element.params {
+            let field_name = format!(
+                "_{}_{}",
+                field_name_part(&element.name),
+                field_name_part(&name)
+            );
+            write!(
+                json,
+                ",{}:{}",
+                json_string(&field_name),
+                json_string(&value)
+            )?;
+        }
+    }
+
+    json.push('}');
+    Ok(json)
+}
+
+// additional field names may only hold word characters, dots and dashes
+fn field_name_part(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            c if c.is_ascii_alphanumeric() || c == '.' || c == '-' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+fn connect(server: &str, retry: &RetryPolicy) -> io::Result<TcpStream> {
+    retry.connect(server.to_socket_addrs()?, server)
+}
+
+fn invalid_input(message: &str) -> Box<dyn Error> {
+    Box::new(io::Error::new(io::ErrorKind::InvalidInput, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Facility, Severity};
+    use flate2::read::{GzDecoder, ZlibDecoder};
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    fn entry(message: &str) -> Entry {
+        Entry::new(Facility::User, Severity::Info, "app", "msgid", message).unwrap()
+    }
+
+    fn collector() -> (UdpSocket, String) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+            .unwrap();
+        let server = socket.local_addr().unwrap().to_string();
+        (socket, server)
+    }
+
+    fn receive(socket: &UdpSocket) -> Vec<u8> {
+        let mut buffer = vec![0; 65536];
+        let received = socket.recv(&mut buffer).unwrap();
+        buffer.truncate(received);
+        buffer
+    }
+
+    #[test]
+    fn splits_large_messages_into_chunks() {
+        let (socket, server) = collector();
+        let target = GelfTarget::builder(&server)
+            .chunk_size(100)
+            .build()
+            .unwrap();
+        let message = "x".repeat(1000);
+
+        target.log(&entry(&message)).unwrap();
+
+        let first = receive(&socket);
+        let count = first[11] as usize;
+        assert!(count > 1);
+
+        let mut chunks = vec![first];
+        for _ in 1..count {
+            chunks.push(receive(&socket));
+        }
+
+        // every chunk shares the message id, and they arrive in order over
+        // loopback
+        let mut payload = Vec::new();
+        for (sequence, chunk) in chunks.iter().enumerate() {
+            assert!(chunk.len() <= 100);
+            assert_eq!(&chunk[..2], &[0x1e, 0x0f]);
+            assert_eq!(&chunk[2..10], &chunks[0][2..10]);
+            assert_eq!(chunk[10] as usize, sequence);
+            assert_eq!(chunk[11] as usize, count);
+            payload.extend_from_slice(&chunk[CHUNK_HEADER_SIZE..]);
+        }
+
+        let json = String::from_utf8(payload).unwrap();
+        assert!(json.starts_with("{\"version\":\"1.1\""), "{}", json);
+        assert!(json.contains(&format!("\"short_message\":\"{}\"", message)));
+    }
+
+    #[test]
+    fn refuses_messages_needing_too_many_chunks() {
+        let (_socket, server) = collector();
+        let target = GelfTarget::builder(&server)
+            .chunk_size(CHUNK_HEADER_SIZE + 1)
+            .build()
+            .unwrap();
+
+        assert!(target.log(&entry(&"x".repeat(MAX_CHUNKS + 1))).is_err());
+    }
+
+    #[test]
+    fn compresses_with_zlib_and_gzip() {
+        let (socket, server) = collector();
+
+        for compression in [GelfCompression::Zlib, GelfCompression::Gzip] {
+            let target = GelfTarget::builder(&server)
+                .compression(compression)
+                .build()
+                .unwrap();
+            target.log(&entry("compressed")).unwrap();
+
+            let payload = receive(&socket);
+            let mut json = String::new();
+            match compression {
+                GelfCompression::Zlib => ZlibDecoder::new(&payload[..])
+                    .read_to_string(&mut json)
+                    .unwrap(),
+                _ => GzDecoder::new(&payload[..])
+                    .read_to_string(&mut json)
+                    .unwrap(),
+            };
+
+            assert!(
+                json.contains("\"short_message\":\"compressed\""),
+                "{}",
+                json
+            );
+        }
+    }
+
+    #[test]
+    fn terminates_tcp_messages_with_a_null_byte() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = listener.local_addr().unwrap().to_string();
+        let received = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut received = Vec::new();
+            stream.read_to_end(&mut received).unwrap();
+            received
+        });
+
+        let target = GelfTarget::builder(&server)
+            .transport(GelfTransport::Tcp)
+            .build()
+            .unwrap();
+        target.log(&entry("first")).unwrap();
+        target.log(&entry("second")).unwrap();
+        drop(target);
+
+        let received = received.join().unwrap();
+        let messages: Vec<&[u8]> = received.split(|&b| b == 0).collect();
+        assert_eq!(messages.len(), 3);
+        assert!(messages[2].is_empty());
+        assert!(String::from_utf8_lossy(messages[0]).contains("\"short_message\":\"first\""));
+        assert!(String::from_utf8_lossy(messages[1]).contains("\"short_message\":\"second\""));
+    }
+
+    #[test]
+    fn reconnects_over_tcp_after_the_collector_restarts() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = listener.local_addr().unwrap().to_string();
+        let target = GelfTarget::builder(&server)
+            .transport(GelfTransport::Tcp)
+            .build()
+            .unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        drop(stream);
+        drop(listener);
+
+        let listener = TcpListener::bind(&server).unwrap();
+        let received = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut received = Vec::new();
+            stream.read_to_end(&mut received).unwrap();
+            received
+        });
+
+        target.log(&entry("after")).unwrap();
+        drop(target);
+
+        let received = received.join().unwrap();
+        assert!(String::from_utf8_lossy(&received).contains("\"short_message\":\"after\""));
+    }
+}
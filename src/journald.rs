@@ -1,40 +1,267 @@
 use stumpless_sys::*;
 
+use std::cell::RefCell;
 use std::error::Error;
 use std::ffi::CString;
+use std::os::raw::c_char;
 
-use crate::StumplessError;
-use crate::Target;
+use crate::journald_fields::{entry_fields, journald_field_name, param_field_name, FieldNaming};
+use crate::target::{add_entry_to_pointer, TargetPointer};
+use crate::{Entry, Facility, Severity, StumplessError, Target};
+
+// the field names of the params of the entry being logged on this thread, by
+// element and then param index, for name_param to hand to stumpless
+thread_local! {
+    static PARAM_FIELD_NAMES: RefCell<Vec<Vec<String>>> = const { RefCell::new(Vec::new()) };
+}
 
 pub struct JournaldTarget {
-    target: *mut stumpless_target,
+    target: TargetPointer,
+    name: String,
+    field_naming: FieldNaming,
+}
+
+pub struct JournaldTargetBuilder {
+    name: String,
+    field_naming: FieldNaming,
 }
 
 impl JournaldTarget {
     pub fn new() -> Result<Self, Box<dyn Error>> {
-        let target_name = CString::new("stumpless-cli")?;
+        JournaldTarget::builder().build()
+    }
+
+    pub fn builder() -> JournaldTargetBuilder {
+        JournaldTargetBuilder {
+            name: String::from("stumpless-cli"),
+            field_naming: FieldNaming::ElementParam,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    // the journal fields for an entry
+    pub fn fields(&self, entry: &Entry) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+        entry_fields(entry, &self.name, &self.field_naming)
+    }
+
+    // sends the fields as an entry of their own, with MESSAGE, PRIORITY,
+    // SYSLOG_FACILITY and SYSLOG_IDENTIFIER filling in the entry and every
+    // other field passed through under its own name
+    pub fn send_fields(&self, fields: &[(String, String)]) -> Result<usize, Box<dyn Error>> {
+        let field = |name: &str| {
+            fields
+                .iter()
+                .find(|(field_name, _)| field_name == name)
+                .map(|(_, value)| value.as_str())
+        };
+        let number = |name: &str| field(name).and_then(|value| value.parse::<i32>().ok());
+
+        let entry = Entry::new(
+            Facility::User,
+            Severity::Info,
+            field("SYSLOG_IDENTIFIER").unwrap_or(&self.name),
+            "-",
+            field("MESSAGE").unwrap_or_default(),
+        )?;
+        entry.set_prival(
+            number("SYSLOG_FACILITY").unwrap_or(1) << 3 | number("PRIORITY").unwrap_or(6),
+        )?;
+
+        let mut names = Vec::new();
+        for (name, value) in fields {
+            if !matches!(
+                name.as_str(),
+                "MESSAGE" | "PRIORITY" | "SYSLOG_FACILITY" | "SYSLOG_IDENTIFIER"
+            ) {
+                if names.is_empty() {
+                    entry.add_element("fields")?;
+                }
+                let field_name = journald_field_name(name);
+                entry.add_param("fields", &field_name, value)?;
+                names.push(field_name);
+            }
+        }
+
+        self.add_entry(&entry, vec![names])
+    }
+
+    // stumpless asks name_param for the field name of each param while the
+    // entry is being added, which happens on this thread
+    fn add_entry(&self, entry: &Entry, names: Vec<Vec<String>>) -> Result<usize, Box<dyn Error>> {
+        set_param_namers(entry)?;
+
+        PARAM_FIELD_NAMES.with(|field_names| *field_names.borrow_mut() = names);
+        let add_result = add_entry_to_pointer(self.target.0, entry);
+        PARAM_FIELD_NAMES.with(|field_names| field_names.borrow_mut().clear());
+
+        add_result
+    }
+}
+
+impl JournaldTargetBuilder {
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = name.to_string();
+        self
+    }
+
+    pub fn field_naming(mut self, field_naming: FieldNaming) -> Self {
+        self.field_naming = field_naming;
+        self
+    }
+
+    pub fn build(self) -> Result<JournaldTarget, Box<dyn Error>> {
+        let target_name = CString::new(self.name.as_str())?;
         let journald_target = unsafe { stumpless_open_journald_target(target_name.as_ptr()) };
 
         if journald_target.is_null() {
-            Err(Box::new(StumplessError))
-        } else {
-            Ok(JournaldTarget {
-                target: journald_target,
-            })
+            return Err(Box::new(StumplessError));
         }
+
+        Ok(JournaldTarget {
+            target: TargetPointer(journald_target),
+            name: self.name,
+            field_naming: self.field_naming,
+        })
     }
 }
 
 impl Target for JournaldTarget {
-    fn get_pointer(&self) -> *mut stumpless_target {
-        self.target
+    fn log(&self, entry: &Entry) -> Result<usize, Box<dyn Error>> {
+        let names = entry
+            .get_elements()?
+            .iter()
+            .map(|element| {
+                element
+                    .params
+                    .iter()
+                    .map(|(param_name, _)| {
+                        param_field_name(&element.name, param_name, &self.field_naming)
+                    })
+                    .collect()
+            })
+            .collect();
+
+        self.add_entry(entry, names)
+    }
+
+    fn native_pointer(&self) -> Option<*mut stumpless_target> {
+        Some(self.target.0)
     }
 }
 
 impl Drop for JournaldTarget {
     fn drop(&mut self) {
         unsafe {
-            stumpless_close_journald_target(self.target);
+            stumpless_close_journald_target(self.target.0);
+        }
+    }
+}
+
+// the namer is kept by each param, so this also changes how the entry is
+// named if it is later sent to journald some other way
+fn set_param_namers(entry: &Entry) -> Result<(), Box<dyn Error>> {
+    let entry = entry.get_pointer();
+
+    for i in 0..unsafe { stumpless_get_element_count(entry) } {
+        let element = unsafe { stumpless_get_element_by_index(entry, i) };
+        if element.is_null() {
+            return Err(Box::new(StumplessError));
+        }
+
+        for j in 0..unsafe { stumpless_get_param_count(element) } {
+            let param = unsafe { stumpless_get_param_by_index(element, j) };
+            if param.is_null()
+                || unsafe { stumpless_set_param_journald_namer(param, Some(name_param)) }.is_null()
+            {
+                return Err(Box::new(StumplessError));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// like snprintf, writes as much of the name as fits and returns its full
+// length, so that stumpless can ask again with a larger buffer
+unsafe extern "C" fn name_param(
+    _entry: *const stumpless_entry,
+    element_index: usize,
+    param_index: usize,
+    destination: *mut c_char,
+    size: usize,
+) -> usize {
+    PARAM_FIELD_NAMES.with(|field_names| {
+        let field_names = field_names.borrow();
+        let name = field_names
+            .get(element_index)
+            .and_then(|params| params.get(param_index))
+            .map_or("FIELD", String::as_str);
+
+        let copied = name.len().min(size);
+        std::ptr::copy_nonoverlapping(name.as_ptr(), destination.cast(), copied);
+        if copied < size {
+            *destination.add(copied) = 0;
         }
+
+        name.len()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn named(
+        names: Vec<Vec<String>>,
+        element_index: usize,
+        param_index: usize,
+        size: usize,
+    ) -> (usize, Vec<u8>) {
+        PARAM_FIELD_NAMES.with(|field_names| *field_names.borrow_mut() = names);
+
+        let mut destination = vec![0xffu8; size];
+        let length = unsafe {
+            name_param(
+                std::ptr::null(),
+                element_index,
+                param_index,
+                destination.as_mut_ptr().cast(),
+                size,
+            )
+        };
+
+        (length, destination)
+    }
+
+    #[test]
+    fn names_params_by_index() {
+        let names = vec![
+            vec![String::from("AUTH_UID")],
+            vec![String::from("ORIGIN_IP"), String::from("ORIGIN_PORT")],
+        ];
+
+        let (length, destination) = named(names, 1, 1, 32);
+
+        assert_eq!(length, 11);
+        assert_eq!(&destination[..12], b"ORIGIN_PORT\0");
+    }
+
+    #[test]
+    fn reports_the_length_of_names_that_do_not_fit() {
+        let (length, destination) = named(vec![vec![String::from("ORIGIN_PORT")]], 0, 0, 4);
+
+        assert_eq!(length, 11);
+        assert_eq!(destination, b"ORIG");
+    }
+
+    #[test]
+    fn names_unknown_params_field() {
+        let (length, destination) = named(Vec::new(), 0, 0, 8);
+
+        assert_eq!(length, 5);
+        assert_eq!(&destination[..6], b"FIELD\0");
     }
 }
@@ -0,0 +1,188 @@
+use std::error::Error;
+
+use crate::Entry;
+
+// given the element and param names, returns the field name to use
+pub type FieldNamer = Box<dyn Fn(&str, &str) -> String + Send + Sync>;
+
+pub enum FieldNaming {
+    // param uid of element auth becomes AUTH_UID
+    ElementParam,
+    // param uid of element auth becomes UID
+    Param,
+    Custom(FieldNamer),
+}
+
+// the journal fields for an entry, with name standing in for entries
+// without an app name
+pub(crate) fn entry_fields(
+    entry: &Entry,
+    name: &str,
+    field_naming: &FieldNaming,
+) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    let prival = entry.get_prival()?;
+    let mut fields = vec![
+        (String::from("MESSAGE"), entry.get_message()?),
+        (String::from("PRIORITY"), (prival & 0x07).to_string()),
+        (String::from("SYSLOG_FACILITY"), (prival >> 3).to_string()),
+    ];
+
+    let app_name = entry.get_app_name()?;
+    let identifier = if app_name.is_empty() || app_name == "-" {
+        name.to_string()
+    } else {
+        app_name
+    };
+    fields.push((String::from("SYSLOG_IDENTIFIER"), identifier));
+
+    let msgid = entry.get_msgid()?;
+    if !msgid.is_empty() && msgid != "-" {
+        fields.push((String::from("SYSLOG_MSGID"), msgid));
+    }
+
+    if let Some(procid) = entry.get_procid()? {
+        fields.push((String::from("SYSLOG_PID"), procid));
+    }
+
+    for element in entry.get_elements()? {
+        for (param_name, param_value) in &element.params {
+            let field_name = param_field_name(&element.name, param_name, field_naming);
+            fields.push((field_name, param_value.clone()));
+        }
+    }
+
+    Ok(fields)
+}
+
+pub(crate) fn param_field_name(
+    element_name: &str,
+    param_name: &str,
+    field_naming: &FieldNaming,
+) -> String {
+    let field_name = match field_naming {
+        FieldNaming::ElementParam => format!("{}_{}", element_name, param_name),
+        FieldNaming::Param => param_name.to_string(),
+        FieldNaming::Custom(namer) => namer(element_name, param_name),
+    };
+
+    journald_field_name(&field_name)
+}
+
+// journald only accepts uppercase letters, digits and underscores, and
+// reserves names starting with an underscore for trusted fields
+pub(crate) fn journald_field_name(name: &str) -> String {
+    let field_name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .skip_while(|&c| c == '_' || c.is_ascii_digit())
+        .take(64)
+        .collect();
+
+    if field_name.is_empty() {
+        String::from("FIELD")
+    } else {
+        field_name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Facility, Severity};
+
+    fn entry() -> Entry {
+        let entry =
+            Entry::new(Facility::Auth, Severity::Warning, "sshd", "login", "failed").unwrap();
+        entry.add_element("auth").unwrap();
+        entry.add_param("auth", "uid", "1000").unwrap();
+        entry.add_param("auth", "method", "password").unwrap();
+        entry
+    }
+
+    fn field<'a>(fields: &'a [(String, String)], name: &str) -> Option<&'a str> {
+        fields
+            .iter()
+            .find(|(field_name, _)| field_name == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    #[test]
+    fn uppercases_field_names() {
+        assert_eq!(journald_field_name("auth_uid"), "AUTH_UID");
+        assert_eq!(journald_field_name("Origin2"), "ORIGIN2");
+    }
+
+    #[test]
+    fn replaces_characters_journald_rejects() {
+        assert_eq!(journald_field_name("origin.ip"), "ORIGIN_IP");
+        assert_eq!(journald_field_name("example@32473_id"), "EXAMPLE_32473_ID");
+        assert_eq!(journald_field_name("naïve"), "NA_VE");
+    }
+
+    #[test]
+    fn strips_leading_underscores_and_digits() {
+        assert_eq!(journald_field_name("_trusted"), "TRUSTED");
+        assert_eq!(journald_field_name("2nd_try"), "ND_TRY");
+        assert_eq!(journald_field_name("__"), "FIELD");
+        assert_eq!(journald_field_name(""), "FIELD");
+    }
+
+    #[test]
+    fn limits_field_names_to_64_characters() {
+        assert_eq!(journald_field_name(&"a".repeat(100)), "A".repeat(64));
+    }
+
+    #[test]
+    fn names_params_by_policy() {
+        let custom =
+            FieldNaming::Custom(Box::new(|element, param| format!("{}-{}", param, element)));
+
+        assert_eq!(
+            param_field_name("auth", "uid", &FieldNaming::ElementParam),
+            "AUTH_UID"
+        );
+        assert_eq!(param_field_name("auth", "uid", &FieldNaming::Param), "UID");
+        assert_eq!(param_field_name("auth", "uid", &custom), "UID_AUTH");
+    }
+
+    #[test]
+    fn maps_entries_to_fields() {
+        let fields = entry_fields(&entry(), "stumpless-cli", &FieldNaming::ElementParam).unwrap();
+
+        assert_eq!(field(&fields, "MESSAGE"), Some("failed"));
+        assert_eq!(field(&fields, "PRIORITY"), Some("4"));
+        assert_eq!(field(&fields, "SYSLOG_FACILITY"), Some("4"));
+        assert_eq!(field(&fields, "SYSLOG_IDENTIFIER"), Some("sshd"));
+        assert_eq!(field(&fields, "SYSLOG_MSGID"), Some("login"));
+        assert_eq!(field(&fields, "AUTH_UID"), Some("1000"));
+        assert_eq!(field(&fields, "AUTH_METHOD"), Some("password"));
+        assert_eq!(field(&fields, "SYSLOG_PID"), None);
+    }
+
+    #[test]
+    fn maps_params_with_the_naming_policy() {
+        let fields = entry_fields(&entry(), "stumpless-cli", &FieldNaming::Param).unwrap();
+
+        assert_eq!(field(&fields, "UID"), Some("1000"));
+        assert_eq!(field(&fields, "METHOD"), Some("password"));
+        assert_eq!(field(&fields, "AUTH_UID"), None);
+    }
+
+    #[test]
+    fn falls_back_to_the_target_name_and_leaves_out_nil_fields() {
+        let entry = Entry::new(Facility::User, Severity::Info, "-", "-", "plain").unwrap();
+        entry.set_procid("4242").unwrap();
+
+        let fields = entry_fields(&entry, "stumpless-cli", &FieldNaming::ElementParam).unwrap();
+
+        assert_eq!(field(&fields, "SYSLOG_IDENTIFIER"), Some("stumpless-cli"));
+        assert_eq!(field(&fields, "SYSLOG_MSGID"), None);
+        assert_eq!(field(&fields, "SYSLOG_PID"), Some("4242"));
+    }
+}
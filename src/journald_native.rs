@@ -0,0 +1,287 @@
+use std::error::Error;
+use std::io;
+use std::os::unix::net::UnixDatagram;
+
+#[cfg(target_os = "linux")]
+use crate::cmsg::send_with_control;
+use crate::journald_fields::{entry_fields, FieldNaming};
+use crate::{Entry, Target};
+
+pub const DEFAULT_JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+// speaks the native journal protocol directly, so it needs neither
+// libsystemd nor a running journald to be exercised
+pub struct JournaldSocketTarget {
+    name: String,
+    field_naming: FieldNaming,
+    socket_path: String,
+    socket: UnixDatagram,
+}
+
+pub struct JournaldSocketTargetBuilder {
+    name: String,
+    field_naming: FieldNaming,
+    socket_path: String,
+}
+
+impl JournaldSocketTarget {
+    pub fn new() -> Result<Self, Box<dyn Error>> {
+        JournaldSocketTarget::builder().build()
+    }
+
+    pub fn builder() -> JournaldSocketTargetBuilder {
+        JournaldSocketTargetBuilder {
+            name: String::from("stumpless-cli"),
+            field_naming: FieldNaming::ElementParam,
+            socket_path: String::from(DEFAULT_JOURNALD_SOCKET),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn socket_path(&self) -> &str {
+        &self.socket_path
+    }
+
+    pub fn fields(&self, entry: &Entry) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+        entry_fields(entry, &self.name, &self.field_naming)
+    }
+
+    pub fn send_fields(&self, fields: &[(String, String)]) -> Result<usize, Box<dyn Error>> {
+        let datagram = encode_fields(fields);
+
+        match self.socket.send_to(&datagram, &self.socket_path) {
+            Ok(_) => Ok(datagram.len()),
+            // journald reads entries too big for a datagram from a memfd instead
+            Err(error)
+                if error.raw_os_error() == Some(libc::EMSGSIZE)
+                    || error.raw_os_error() == Some(libc::ENOBUFS) =>
+            {
+                send_memfd(&self.socket_path, &datagram)?;
+                Ok(datagram.len())
+            }
+            Err(error) => Err(Box::new(error)),
+        }
+    }
+}
+
+impl JournaldSocketTargetBuilder {
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = name.to_string();
+        self
+    }
+
+    pub fn field_naming(mut self, field_naming: FieldNaming) -> Self {
+        self.field_naming = field_naming;
+        self
+    }
+
+    pub fn socket_path(mut self, socket_path: &str) -> Self {
+        self.socket_path = socket_path.to_string();
+        self
+    }
+
+    pub fn build(self) -> Result<JournaldSocketTarget, Box<dyn Error>> {
+        Ok(JournaldSocketTarget {
+            name: self.name,
+            field_naming: self.field_naming,
+            socket_path: self.socket_path,
+            socket: UnixDatagram::unbound()?,
+        })
+    }
+}
+
+impl Target for JournaldSocketTarget {
+    fn log(&self, entry: &Entry) -> Result<usize, Box<dyn Error>> {
+        self.send_fields(&self.fields(entry)?)
+    }
+}
+
+// values with a newline in them can't use the NAME=value form, and are
+// instead sent as the name, a little endian 64 bit length and the raw value
+fn encode_fields(fields: &[(String, String)]) -> Vec<u8> {
+    let mut datagram = Vec::new();
+
+    for (name, value) in fields {
+        datagram.extend_from_slice(name.as_bytes());
+
+        if value.contains('\n') {
+            datagram.push(b'\n');
+            datagram.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        } else {
+            datagram.push(b'=');
+        }
+
+        datagram.extend_from_slice(value.as_bytes());
+        datagram.push(b'\n');
+    }
+
+    datagram
+}
+
+#[cfg(target_os = "linux")]
+fn send_memfd(socket_path: &str, data: &[u8]) -> io::Result<()> {
+    use std::fs::File;
+    use std::io::Write;
+    use std::os::unix::io::{AsRawFd, FromRawFd};
+
+    let fd = unsafe {
+        libc::memfd_create(
+            b"stumpless-journal\0".as_ptr().cast(),
+            libc::MFD_ALLOW_SEALING | libc::MFD_CLOEXEC,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut file = unsafe { File::from_raw_fd(fd) };
+    file.write_all(data)?;
+
+    // journald refuses descriptors that could still change under it
+    let seals = libc::F_SEAL_SHRINK | libc::F_SEAL_GROW | libc::F_SEAL_WRITE | libc::F_SEAL_SEAL;
+    if unsafe { libc::fcntl(fd, libc::F_ADD_SEALS, seals) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let socket = UnixDatagram::unbound()?;
+    socket.connect(socket_path)?;
+
+    send_with_control(&socket, &[], libc::SCM_RIGHTS, file.as_raw_fd())?;
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn send_memfd(_socket_path: &str, _data: &[u8]) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "entries too large for a datagram need memfd, which is only supported on Linux",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::io::{Read, Seek, SeekFrom};
+    use std::os::unix::io::{AsRawFd, FromRawFd};
+    use std::path::PathBuf;
+
+    struct Journal {
+        path: PathBuf,
+        socket: UnixDatagram,
+    }
+
+    impl Journal {
+        fn bind(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "stumpless-journal-{}-{}",
+                name,
+                std::process::id()
+            ));
+            let _ = std::fs::remove_file(&path);
+
+            Journal {
+                socket: UnixDatagram::bind(&path).unwrap(),
+                path,
+            }
+        }
+
+        fn target(&self) -> JournaldSocketTarget {
+            JournaldSocketTarget::builder()
+                .socket_path(&self.path.to_string_lossy())
+                .build()
+                .unwrap()
+        }
+
+        fn receive(&self) -> Vec<u8> {
+            let mut buffer = vec![0; 65536];
+            let received = self.socket.recv(&mut buffer).unwrap();
+            buffer.truncate(received);
+            buffer
+        }
+
+        // reads the memfd passed along with an empty datagram
+        #[cfg(target_os = "linux")]
+        fn receive_memfd(&self) -> Vec<u8> {
+            let mut control = [0u64; 8];
+            let mut header: libc::msghdr = unsafe { std::mem::zeroed() };
+            header.msg_control = control.as_mut_ptr().cast();
+            header.msg_controllen = std::mem::size_of_val(&control) as _;
+
+            let fd = unsafe {
+                assert!(libc::recvmsg(self.socket.as_raw_fd(), &mut header, 0) >= 0);
+                let control_header = libc::CMSG_FIRSTHDR(&header);
+                assert!(!control_header.is_null());
+                assert_eq!((*control_header).cmsg_type, libc::SCM_RIGHTS);
+                std::ptr::read_unaligned(libc::CMSG_DATA(control_header) as *const libc::c_int)
+            };
+
+            let mut file = unsafe { File::from_raw_fd(fd) };
+            let seals = unsafe { libc::fcntl(fd, libc::F_GET_SEALS) };
+            assert_eq!(seals & libc::F_SEAL_WRITE, libc::F_SEAL_WRITE);
+
+            // the offset is shared with the sender, which left it at the end
+            let mut contents = Vec::new();
+            file.seek(SeekFrom::Start(0)).unwrap();
+            file.read_to_end(&mut contents).unwrap();
+            contents
+        }
+    }
+
+    impl Drop for Journal {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+
+    fn fields(fields: &[(&str, &str)]) -> Vec<(String, String)> {
+        fields
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn sends_fields_as_name_value_lines() {
+        let journal = Journal::bind("plain");
+        let sent = journal
+            .target()
+            .send_fields(&fields(&[("MESSAGE", "hello"), ("PRIORITY", "6")]))
+            .unwrap();
+
+        let datagram = journal.receive();
+        assert_eq!(datagram, b"MESSAGE=hello\nPRIORITY=6\n");
+        assert_eq!(sent, datagram.len());
+    }
+
+    #[test]
+    fn sends_values_with_newlines_with_their_length() {
+        let journal = Journal::bind("binary");
+        journal
+            .target()
+            .send_fields(&fields(&[("MESSAGE", "two\nlines"), ("PRIORITY", "6")]))
+            .unwrap();
+
+        let mut expected = b"MESSAGE\n".to_vec();
+        expected.extend_from_slice(&9u64.to_le_bytes());
+        expected.extend_from_slice(b"two\nlines\nPRIORITY=6\n");
+        assert_eq!(journal.receive(), expected);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn sends_large_entries_through_a_memfd() {
+        let journal = Journal::bind("memfd");
+        let message = "x".repeat(8 * 1024 * 1024);
+        let large_fields = fields(&[("MESSAGE", &message)]);
+
+        let sent = journal.target().send_fields(&large_fields).unwrap();
+
+        let contents = journal.receive_memfd();
+        assert!(contents == encode_fields(&large_fields));
+        assert_eq!(sent, contents.len());
+    }
+}
@@ -1,8 +1,14 @@
 use regex::Regex;
 use std::error::Error;
 
+mod async_target;
+pub use crate::async_target::{AsyncTarget, AsyncTargetBuilder, OverflowPolicy};
+
+mod dedup;
+pub use crate::dedup::{DedupTarget, DedupTargetBuilder};
+
 mod entry;
-pub use crate::entry::{add_entry, Entry};
+pub use crate::entry::{add_entry, Element, Entry};
 
 mod error;
 pub use crate::error::{perror, StumplessError};
@@ -10,29 +16,108 @@ pub use crate::error::{perror, StumplessError};
 mod facility;
 pub use crate::facility::Facility;
 
+mod format;
+pub use crate::format::Format;
+
+mod failover;
+pub use crate::failover::{FailoverTarget, FailoverTargetBuilder};
+
 mod file;
-pub use crate::file::FileTarget;
+pub use crate::file::{FileTarget, FileTargetBuilder, SyncPolicy};
+#[cfg(unix)]
+pub use crate::file::reopen_file_targets_on_sighup;
+
+mod rate_limit;
+pub use crate::rate_limit::{RateLimitKey, RateLimitedTarget, RateLimitedTargetBuilder};
+
+mod redact;
+pub use crate::redact::{RedactingTarget, RedactingTargetBuilder, RedactionRule};
+
+mod rotating_file;
+pub use crate::rotating_file::{RotatingFileTarget, RotationInterval, RotationNaming};
+
+mod sampling;
+pub use crate::sampling::{CorrelationKey, CorrelationKeyFn, SamplingTarget, SamplingTargetBuilder};
 
 mod severity;
 pub use crate::severity::Severity;
 
+mod spool;
+pub use crate::spool::{SpoolTarget, SpoolTargetBuilder};
+
 mod target;
-pub use crate::target::{add_message, Target};
+pub use crate::target::{add_message, set_current_target, stump, stumplog, NativeTarget, Target};
+
+mod timestamp;
+
+#[cfg(all(
+    target_os = "linux",
+    any(feature = "journald-native", feature = "socket")
+))]
+mod cmsg;
+
+#[cfg(feature = "gelf")]
+mod gelf;
+#[cfg(feature = "gelf")]
+pub use crate::gelf::{
+    GelfCompression, GelfTarget, GelfTargetBuilder, GelfTransport, DEFAULT_GELF_PORT,
+};
 
 #[cfg(feature = "journald")]
 mod journald;
 #[cfg(feature = "journald")]
-pub use crate::journald::JournaldTarget;
+pub use crate::journald::{JournaldTarget, JournaldTargetBuilder};
+
+#[cfg(any(feature = "journald", feature = "journald-native"))]
+mod journald_fields;
+#[cfg(any(feature = "journald", feature = "journald-native"))]
+pub use crate::journald_fields::{FieldNamer, FieldNaming};
+
+#[cfg(feature = "journald-native")]
+mod journald_native;
+#[cfg(feature = "journald-native")]
+pub use crate::journald_native::{
+    JournaldSocketTarget, JournaldSocketTargetBuilder, DEFAULT_JOURNALD_SOCKET,
+};
 
 #[cfg(feature = "network")]
 mod network;
 #[cfg(feature = "network")]
-pub use crate::network::NetworkTarget;
+pub use crate::network::{
+    ConnectionState, ConnectionStats, Framing, NetworkTarget, NetworkTargetBuilder,
+    DEFAULT_TCP_PORT,
+};
+
+#[cfg(any(feature = "gelf", feature = "network", feature = "tls"))]
+mod retry;
+#[cfg(any(feature = "gelf", feature = "network", feature = "tls"))]
+pub use crate::retry::RetryPolicy;
+
+#[cfg(any(feature = "gelf", feature = "network", feature = "tls"))]
+mod server;
 
 #[cfg(feature = "socket")]
 mod socket;
 #[cfg(feature = "socket")]
-pub use crate::socket::SocketTarget;
+pub use crate::socket::{
+    Credentials, SocketTarget, SocketTargetBuilder, SocketType, DEFAULT_SOCKET_NAMES,
+};
+
+#[cfg(feature = "testing")]
+pub mod testing;
+
+#[cfg(feature = "tls")]
+mod tls;
+#[cfg(feature = "tls")]
+pub use crate::tls::{TlsTarget, TlsTargetBuilder, DEFAULT_CA_BUNDLES, DEFAULT_TLS_PORT};
+
+#[cfg(feature = "tokio")]
+mod tokio_target;
+#[cfg(feature = "tokio")]
+pub use crate::tokio_target::{
+    add_entry_async, AsyncTargetHandle, LogFuture, Protocol, TokioNetworkTarget,
+    TokioNetworkTargetBuilder, TokioTarget,
+};
 
 #[cfg(feature = "wel")]
 mod wel;
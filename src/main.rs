@@ -1,15 +1,23 @@
 use clap::{command, Arg};
 use itertools::Itertools;
-use stumpless::{add_entry, Entry, Facility, FileTarget, prival_from_string, Severity};
+use std::error::Error;
+use std::io::{self, BufRead};
+use stumpless::{add_entry, DedupTarget, Entry, Facility, FailoverTarget, FileTarget, Format, prival_from_string, RateLimitedTarget, RedactingTarget, RedactionRule, Severity, Target};
+
+#[cfg(feature = "journald")]
+use std::fs;
 
 #[cfg(feature = "journald")]
 use stumpless::JournaldTarget;
 
 #[cfg(feature = "network")]
-use stumpless::NetworkTarget;
+use stumpless::{Framing, NetworkTarget};
 
 #[cfg(feature = "socket")]
-use stumpless::SocketTarget;
+use stumpless::{Credentials, SocketTarget, SocketType};
+
+#[cfg(feature = "tls")]
+use stumpless::TlsTarget;
 
 #[cfg(feature = "wel")]
 use clap::ValueSource;
@@ -24,9 +32,49 @@ fn main() {
         in scripts that send multiple messages, for example the script's own \
         process id.\
         \n\n\
-        Note that some logging infrastructure (for example systemd when \
-        listening on /dev/log) may overwrite this value, for example with the \
-        one derived from the connecting socket.";
+        When logging to a socket, a numeric id is also passed to the daemon as \
+        the credentials of the sender, so that infrastructure like systemd \
+        listening on /dev/log does not overwrite it with the one derived from \
+        the connecting socket. This requires privileges if the id is not the \
+        PID of the CLI process, and without them only the entry carries it. \
+        The same goes for an id that no running process has.";
+
+    let journald_long_help = "\
+        Log the entry to the journald system.\
+        \n\n\
+        When the optional argument file is specified, journal fields are read \
+        from it instead, or from stdin if it is -. Each line holds a single \
+        KEY=value field, and blank lines separate entries. MESSAGE, PRIORITY \
+        and SYSLOG_IDENTIFIER are taken from the message and options given if \
+        the file leaves them out.";
+
+    let framing_long_help = "\
+        Octet counting precedes each entry with its length, and \
+        non-transparent framing follows each entry with a newline. Receivers \
+        differ in which of the two they accept. With non-transparent framing, \
+        newlines and carriage returns within an entry are escaped as #012 and \
+        #015 respectively.";
+
+    let fallback_long_help = "\
+        Rather than logging to every target given, the targets are treated as \
+        a chain of fallbacks in the order they appear on the command line, \
+        for example --tcp4 collector --socket --log-file /var/log/backup.log \
+        to fall back to the local syslog socket and then a file if the \
+        collector is unavailable. A target that cannot be opened is left out \
+        of the chain, and the exit status is still non-zero.";
+
+    let rate_limit_long_help = "\
+        The rate is a number of messages per second, minute or hour, for \
+        example 100/s or 600/m. Short bursts of up to a second's worth are let \
+        through, and once messages are accepted again after some were dropped, \
+        an entry saying how many were suppressed is logged ahead of them. Each \
+        target is limited separately, unless --fallback is given.";
+
+    let redact_long_help = "\
+        AWS keys, JWTs, password= values and credit card numbers are replaced \
+        with [REDACTED] in the message and structured data of each entry. \
+        Further regular expressions to redact matches of can be given with \
+        --redact-pattern.";
 
     let wel_install_long_help = "\
         Having the event source information installed is required for the \
@@ -65,7 +113,14 @@ fn main() {
             Arg::new("journald")
                 .short('j')
                 .long("journald")
-                .help("Log the entry to the journald system.")
+                .takes_value(true)
+                .value_name("file")
+                .min_values(0)
+                .multiple_values(false)
+                .require_equals(true)
+                .default_missing_value("")
+                .help("Log the entry to the journald system, or the KEY=value fields read from the file (or - for stdin).")
+                .long_help(journald_long_help)
                 .required(false)
         )
         .arg(
@@ -77,8 +132,18 @@ fn main() {
                 .min_values(0)
                 .multiple_values(false)
                 .require_equals(true)
-                .default_missing_value("/dev/log")
-                .help("Log to the provided socket, defaulting to /dev/log.")
+                .default_missing_value("")
+                .help("Log to the provided socket, defaulting to the first local syslog socket found.")
+                .required(false)
+        )
+        .arg(
+            Arg::new("socket-type")
+                .long("socket-type")
+                .takes_value(true)
+                .value_name("type")
+                .possible_values(["dgram", "stream"])
+                .requires("socket")
+                .help("The type of socket to log to, defaulting to dgram. Ignored when the socket is discovered.")
                 .required(false)
         )
         .arg(
@@ -90,6 +155,24 @@ fn main() {
                 .help("Log the entry to the given file.")
                 .required(false)
         )
+        .arg(
+            Arg::new("log-file-max-size")
+                .long("log-file-max-size")
+                .takes_value(true)
+                .value_name("size")
+                .requires("log-file")
+                .help("Rotate the log file once it reaches the given size, for example 512K or 10M.")
+                .required(false)
+        )
+        .arg(
+            Arg::new("log-file-keep")
+                .long("log-file-keep")
+                .takes_value(true)
+                .value_name("count")
+                .requires("log-file-max-size")
+                .help("The number of rotated log files to keep, defaulting to 5. Requires --log-file-max-size.")
+                .required(false)
+        )
         .arg(
             Arg::new("tcp4")
                 .short('c')
@@ -99,6 +182,107 @@ fn main() {
                 .help("Send the entry to the given server using TCP over IPv4.")
                 .required(false)
         )
+        .arg(
+            Arg::new("framing")
+                .long("framing")
+                .takes_value(true)
+                .value_name("method")
+                .possible_values(["octet-counting", "non-transparent"])
+                .requires("tcp4")
+                .help("The RFC 6587 framing to send entries over TCP with, defaulting to non-transparent.")
+                .long_help(framing_long_help)
+                .required(false)
+        )
+        .arg(
+            Arg::new("octet-count")
+                .long("octet-count")
+                .requires("tcp4")
+                .conflicts_with("framing")
+                .help("Use octet counting framing, the same as --framing=octet-counting.")
+                .required(false)
+        )
+        .arg(
+            Arg::new("tls-server")
+                .long("tls-server")
+                .takes_value(true)
+                .value_name("server")
+                .help("Send the entry to the given server using syslog over TLS, on port 6514 unless another is given.")
+                .required(false)
+        )
+        .arg(
+            Arg::new("tls-ca")
+                .long("tls-ca")
+                .takes_value(true)
+                .value_name("file")
+                .requires("tls-server")
+                .help("The CA bundle to verify the TLS server with, defaulting to the system bundle.")
+                .required(false)
+        )
+        .arg(
+            Arg::new("tls-cert")
+                .long("tls-cert")
+                .takes_value(true)
+                .value_name("file")
+                .requires_all(&["tls-server", "tls-key"])
+                .help("The client certificate to present to the TLS server.")
+                .required(false)
+        )
+        .arg(
+            Arg::new("tls-key")
+                .long("tls-key")
+                .takes_value(true)
+                .value_name("file")
+                .requires("tls-cert")
+                .help("The private key of the client certificate.")
+                .required(false)
+        )
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .takes_value(true)
+                .value_name("format")
+                .possible_values(["rfc5424", "json"])
+                .help("The format to write entries to log files, TCP and TLS servers in, defaulting to rfc5424. With json, each entry is a single line JSON object, timestamped when it is written.")
+                .required(false)
+        )
+        .arg(
+            Arg::new("fallback")
+                .long("fallback")
+                .help("Log to only the first target that accepts the entry, trying them in the order given.")
+                .long_help(fallback_long_help)
+                .required(false)
+        )
+        .arg(
+            Arg::new("redact")
+                .long("redact")
+                .help("Redact common secrets from entries before logging them.")
+                .long_help(redact_long_help)
+                .required(false)
+        )
+        .arg(
+            Arg::new("redact-pattern")
+                .long("redact-pattern")
+                .takes_value(true)
+                .value_name("pattern")
+                .multiple_occurrences(true)
+                .help("Redact matches of the given regular expression as well as common secrets. Can be given more than once.")
+                .required(false)
+        )
+        .arg(
+            Arg::new("rate-limit")
+                .long("rate-limit")
+                .takes_value(true)
+                .value_name("rate")
+                .help("When reading messages from stdin, drop those beyond the given rate, for example 100/s.")
+                .long_help(rate_limit_long_help)
+                .required(false)
+        )
+        .arg(
+            Arg::new("dedup")
+                .long("dedup")
+                .help("When reading messages from stdin, log repeats of the same message as a single \"last message repeated N times\" entry.")
+                .required(false)
+        )
         .arg(
             Arg::new("windows-event-log")
                 .short('w')
@@ -119,9 +303,8 @@ fn main() {
         )
         .arg(
             Arg::new("message")
-                .help("The message to send in the log entry.")
+                .help("The message to send in the log entry. If it is left out, each line read from stdin is logged instead.")
                 .multiple_values(true)
-                .required_unless("install-wel-default-source")
         )
         .get_matches();
 
@@ -136,58 +319,152 @@ fn main() {
         eprintln!("Windows Event Log functionality is not enabled, ignoring --install-wel-default-source option")
     }
 
-    if cli_matches.occurrences_of("message") == 0 {
+    // options given without a value are empty, so that their position on
+    // the command line is still known for --fallback
+    let journald_file = cli_matches.value_of("journald").filter(|file| !file.is_empty());
+    let message_given = cli_matches.occurrences_of("message") > 0;
+    if !message_given && journald_file.is_none() && cli_matches.is_present("install-wel-default-source") {
         // we are all done if there is no message to log
         return;
     }
 
-    let message_iterator = cli_matches.values_of("message").unwrap();
+    let message = match cli_matches.values_of("message") {
+        Some(message_iterator) => Itertools::intersperse(message_iterator, " ").collect::<String>(),
+        None => String::new(),
+    };
+
+    let prival = cli_matches.value_of("priority")
+        .map(|priority| prival_from_string(priority).expect("could not parse priority"));
+    let procid = cli_matches.value_of("id");
+
+    let new_entry = |message: &str| {
+        let entry = Entry::new(
+            Facility::User,
+            Severity::Alert,
+            "app_name",
+            "msgid",
+            message,
+        )
+        .expect("entry creation failed!");
+
+        if let Some(prival) = prival {
+            entry.set_prival(prival).expect("priority invalid");
+        }
+
+        if let Some(procid) = procid {
+            entry.set_procid(procid).expect("id invalid");
+        }
+
+        entry
+    };
 
-    let message = Itertools::intersperse(message_iterator, " ").collect::<String>();
+    let entry = new_entry(&message);
 
-    let entry = Entry::new(
-        Facility::User,
-        Severity::Alert,
-        "app_name",
-        "msgid",
-        &message,
-    )
-    .expect("entry creation failed!");
+    #[cfg(feature = "journald")]
+    if let Some(journald_file) = journald_file {
+        if let Err(error) = log_journald_fields(journald_file, &entry) {
+            eprintln!("logging the journald fields failed: {}", error);
+            std::process::exit(1);
+        }
+    }
 
-    if cli_matches.is_present("priority") {
-        let priority = cli_matches.value_of("priority").unwrap();
-        let prival = prival_from_string(priority).expect("could not parse priority");
-        entry.set_prival(prival).expect("priority invalid");
+    #[cfg(not(feature = "journald"))]
+    if cli_matches.is_present("journald") {
+        eprintln!("journald logging not enabled, ignoring --journald option");
     }
 
+    if !message_given && journald_file.is_some() {
+        // only journald fields were given
+        return;
+    }
+
+    let format = match cli_matches.value_of("format") {
+        Some("json") => Format::JsonLines,
+        _ => Format::Rfc5424,
+    };
+
+    // targets are collected along with where they were given on the command
+    // line, which is the order they are tried in with --fallback
+    let mut targets: Vec<(usize, Box<dyn Target + Send>)> = Vec::new();
+    // a target that could not be opened is left out, but still fails the run
+    let mut open_failed = false;
+
     if cli_matches.is_present("log-file") {
         let log_filename = cli_matches.value_of("log-file").unwrap();
-        match FileTarget::new(log_filename) {
-            Err(_error) => stumpless::perror("opening the file target failed"),
-            Ok(target) => {
-                if let Err(_error) = add_entry(&target, &entry) {
-                    stumpless::perror("logging to the file target failed");
+        let file_target: Result<Box<dyn Target + Send>, Box<dyn Error>> =
+            if cli_matches.is_present("log-file-max-size") {
+                let mut builder = FileTarget::builder(log_filename).format(format);
+
+                if let Some(max_size) = cli_matches.value_of("log-file-max-size") {
+                    builder = builder.max_size(size_from_string(max_size).expect("could not parse log file size"));
                 }
+
+                if let Some(keep) = cli_matches.value_of("log-file-keep") {
+                    builder = builder.keep(keep.parse().expect("could not parse log file keep count"));
+                }
+
+                builder.build_rotating().map(|target| Box::new(target) as Box<dyn Target + Send>)
+            } else {
+                FileTarget::builder(log_filename).format(format).build().map(|target| Box::new(target) as Box<dyn Target + Send>)
+            };
+
+        match file_target {
+            Err(_error) => {
+                stumpless::perror("opening the file target failed");
+                open_failed = true;
             }
+            Ok(target) => targets.push((cli_matches.index_of("log-file").unwrap(), target)),
         };
     }
 
+    // a journald fields file was already sent on its own above
     #[cfg(feature = "journald")]
-    if cli_matches.is_present("journald") {
-        let journald_target = JournaldTarget::new().unwrap();
-        add_entry(&journald_target, &entry).expect("logging to journald failed!");
-    }
-
-    #[cfg(not(feature = "journald"))]
-    if cli_matches.is_present("journald") {
-        eprintln!("journald logging not enabled, ignoring --journald option");
+    if cli_matches.is_present("journald") && journald_file.is_none() {
+        match JournaldTarget::new() {
+            Err(error) => {
+                eprintln!("opening the journald target failed: {}", error);
+                open_failed = true;
+            }
+            Ok(target) => targets.push((cli_matches.index_of("journald").unwrap(), Box::new(target))),
+        };
     }
 
     #[cfg(feature = "socket")]
     if cli_matches.is_present("socket") {
-        let socket_name = cli_matches.value_of("socket").unwrap();
-        let socket_target = SocketTarget::new(socket_name).unwrap();
-        add_entry(&socket_target, &entry).expect("logging to socket failed!");
+        let socket_type = match cli_matches.value_of("socket-type") {
+            Some("stream") => SocketType::Stream,
+            _ => SocketType::Datagram,
+        };
+        let socket_name = cli_matches.value_of("socket").filter(|name| !name.is_empty());
+        let mut socket_builder = SocketTarget::builder(socket_name.unwrap_or_default())
+            .socket_type(socket_type);
+
+        // a numeric id is also passed to the daemon as the sending pid, as
+        // otherwise it would be replaced with the one from the socket, but
+        // only where the kernel allows it so that unprivileged use still works
+        if let Some(pid) = procid.and_then(|procid| procid.parse::<u32>().ok()) {
+            let credentials = Credentials {
+                pid,
+                ..Credentials::current()
+            };
+
+            if credentials.permitted() {
+                socket_builder = socket_builder.credentials(credentials);
+            }
+        }
+
+        let socket_target = match socket_name {
+            Some(_) => socket_builder.build(),
+            None => socket_builder.discover(),
+        };
+
+        match socket_target {
+            Err(error) => {
+                eprintln!("opening the socket target failed: {}", error);
+                open_failed = true;
+            }
+            Ok(target) => targets.push((cli_matches.index_of("socket").unwrap(), Box::new(target))),
+        };
     }
 
     #[cfg(not(feature = "socket"))]
@@ -195,15 +472,243 @@ fn main() {
         eprintln!("socket logging not enabled, ignoring --socket option");
     }
 
+    #[cfg(feature = "network")]
+    if let Some(tcp4_server) = cli_matches.value_of("tcp4") {
+        let framing = match cli_matches.value_of("framing") {
+            Some("octet-counting") => Framing::OctetCounting,
+            _ if cli_matches.is_present("octet-count") => Framing::OctetCounting,
+            _ => Framing::NonTransparent,
+        };
+        let network_target = NetworkTarget::builder(tcp4_server)
+            .framing(framing)
+            .format(format)
+            .ipv4_only(true)
+            .build();
+
+        match network_target {
+            Err(error) => {
+                eprintln!("connecting to the TCP server failed: {}", error);
+                open_failed = true;
+            }
+            Ok(target) => targets.push((cli_matches.index_of("tcp4").unwrap(), Box::new(target))),
+        };
+    }
+
+    #[cfg(not(feature = "network"))]
+    if cli_matches.is_present("tcp4") {
+        eprintln!("network logging not enabled, ignoring --tcp4 option");
+    }
+
+    #[cfg(feature = "tls")]
+    if let Some(tls_server) = cli_matches.value_of("tls-server") {
+        let mut tls_builder = TlsTarget::builder(tls_server).format(format);
+
+        if let Some(ca_file) = cli_matches.value_of("tls-ca") {
+            tls_builder = tls_builder.ca_file(ca_file);
+        }
+
+        if let (Some(cert_file), Some(key_file)) = (cli_matches.value_of("tls-cert"), cli_matches.value_of("tls-key")) {
+            tls_builder = tls_builder.client_cert(cert_file, key_file);
+        }
+
+        match tls_builder.build() {
+            Err(error) => {
+                eprintln!("connecting to the TLS server failed: {}", error);
+                open_failed = true;
+            }
+            Ok(target) => targets.push((cli_matches.index_of("tls-server").unwrap(), Box::new(target))),
+        };
+    }
+
+    #[cfg(not(feature = "tls"))]
+    if cli_matches.is_present("tls-server") {
+        eprintln!("TLS logging not enabled, ignoring --tls-server option");
+    }
+
     #[cfg(feature = "wel")]
     if cli_matches.value_source("windows-event-log") == Some(ValueSource::CommandLine) {
         let wel_log_name = cli_matches.value_of("windows-event-log").unwrap();
-        let wel_target = WelTarget::new(wel_log_name).unwrap();
-        add_entry(&wel_target, &entry).expect("logging to the Windows Event Log failed!");
+
+        match WelTarget::new(wel_log_name) {
+            Err(_error) => {
+                stumpless::perror("opening the Windows Event Log target failed");
+                open_failed = true;
+            }
+            Ok(target) => targets.push((cli_matches.index_of("windows-event-log").unwrap(), Box::new(target))),
+        };
     }
 
     #[cfg(not(feature = "wel"))]
     if cli_matches.is_present("windows-event-log") {
         eprintln!("Windows Event Log logging is not enabled, ignoring --windows-event-log option");
     }
+
+    let fallback = cli_matches.is_present("fallback");
+    if fallback && open_failed {
+        eprintln!("targets that could not be opened were left out of the fallback chain");
+    }
+    let mut targets: Vec<Box<dyn Target + Send>> = if fallback {
+        targets.sort_by_key(|(index, _)| *index);
+        vec![Box::new(FailoverTarget::new(targets.into_iter().map(|(_, target)| target).collect()))]
+    } else {
+        targets.into_iter().map(|(_, target)| target).collect()
+    };
+
+    if cli_matches.is_present("redact") || cli_matches.is_present("redact-pattern") {
+        let rules: Vec<RedactionRule> = cli_matches.values_of("redact-pattern")
+            .into_iter()
+            .flatten()
+            .map(|pattern| RedactionRule::new(pattern).expect("could not parse redaction pattern"))
+            .collect();
+        targets = targets.into_iter()
+            .map(|target| Box::new(RedactingTarget::builder(target).rules(rules.clone()).build()) as Box<dyn Target + Send>)
+            .collect();
+    }
+
+    if let Some(rate_limit) = cli_matches.value_of("rate-limit") {
+        let rate = rate_from_string(rate_limit).expect("could not parse rate limit");
+        targets = targets.into_iter()
+            .map(|target| Box::new(RateLimitedTarget::new(target, rate)) as Box<dyn Target + Send>)
+            .collect();
+    }
+
+    // repeats are collapsed before they count against the rate limit
+    if cli_matches.is_present("dedup") {
+        let deduplicated = targets.into_iter()
+            .map(|target| DedupTarget::new(target).map(|target| Box::new(target) as Box<dyn Target + Send>))
+            .collect();
+
+        targets = match deduplicated {
+            Ok(targets) => targets,
+            Err(error) => {
+                eprintln!("starting the deduplicating target failed: {}", error);
+                std::process::exit(1);
+            }
+        };
+    }
+
+    let log_entry = |entry: &Entry| {
+        let mut logged = true;
+
+        for target in &targets {
+            if let Err(error) = add_entry(&**target, entry) {
+                if fallback {
+                    eprintln!("logging to every fallback target failed: {}", error);
+                } else {
+                    eprintln!("logging the entry failed: {}", error);
+                }
+                logged = false;
+            }
+        }
+
+        logged
+    };
+
+    let mut failed = open_failed;
+
+    if message_given {
+        failed |= !log_entry(&entry);
+    } else {
+        for line in io::stdin().lock().lines() {
+            match line {
+                Err(error) => {
+                    eprintln!("reading from stdin failed: {}", error);
+                    failed = true;
+                    break;
+                }
+                Ok(line) if line.is_empty() => {}
+                Ok(line) => failed |= !log_entry(&new_entry(&line)),
+            }
+        }
+    }
+
+    // wrappers like --rate-limit and --dedup log what they held back when dropped, which
+    // exiting would skip
+    drop(targets);
+
+    if failed {
+        std::process::exit(1);
+    }
+}
+
+fn size_from_string(size: &str) -> Result<u64, Box<dyn Error>> {
+    let (digits, multiplier) = match size.chars().last() {
+        Some('k') | Some('K') => (&size[..size.len() - 1], 1024),
+        Some('m') | Some('M') => (&size[..size.len() - 1], 1024 * 1024),
+        Some('g') | Some('G') => (&size[..size.len() - 1], 1024 * 1024 * 1024),
+        _ => (size, 1),
+    };
+
+    digits.parse::<u64>()?
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("size too large: {}", size).into())
+}
+
+// rates are a count per second, minute or hour, for example 100/s
+fn rate_from_string(rate: &str) -> Result<f64, Box<dyn Error>> {
+    let (count, period) = rate.split_once('/').unwrap_or((rate, "s"));
+    let seconds = match period {
+        "s" | "sec" => 1.0,
+        "m" | "min" => 60.0,
+        "h" | "hour" => 60.0 * 60.0,
+        _ => return Err(format!("invalid rate period: {}", period).into()),
+    };
+
+    Ok(count.parse::<f64>()? / seconds)
+}
+
+// each block of fields in the file is sent as its own entry
+#[cfg(feature = "journald")]
+fn log_journald_fields(journald_file: &str, entry: &Entry) -> Result<(), Box<dyn Error>> {
+    let journald_target = JournaldTarget::new()?;
+    let contents = if journald_file == "-" {
+        io::read_to_string(io::stdin())
+    } else {
+        fs::read_to_string(journald_file)
+    }?;
+    let entry_fields = journald_target.fields(entry)?;
+
+    for mut fields in journal_fields_from_string(&contents)? {
+        // the entry fills in whatever the file leaves out
+        for (name, value) in &entry_fields {
+            if matches!(name.as_str(), "MESSAGE" | "PRIORITY" | "SYSLOG_IDENTIFIER")
+                && !fields.iter().any(|(field_name, _)| field_name == name) {
+                fields.push((name.clone(), value.clone()));
+            }
+        }
+
+        journald_target.send_fields(&fields)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "journald")]
+type JournalFields = Vec<(String, String)>;
+
+// blank lines separate the field blocks of each entry
+#[cfg(feature = "journald")]
+fn journal_fields_from_string(contents: &str) -> Result<Vec<JournalFields>, Box<dyn Error>> {
+    let mut entries = Vec::new();
+    let mut fields = Vec::new();
+
+    for line in contents.lines() {
+        if line.trim().is_empty() {
+            if !fields.is_empty() {
+                entries.push(std::mem::take(&mut fields));
+            }
+            continue;
+        }
+
+        match line.split_once('=') {
+            Some((name, value)) if !name.is_empty() => fields.push((name.to_string(), value.to_string())),
+            _ => return Err(format!("invalid journald field: {}", line).into()),
+        }
+    }
+
+    if !fields.is_empty() {
+        entries.push(fields);
+    }
+
+    Ok(entries)
 }
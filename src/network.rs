@@ -1,40 +1,421 @@
-use stumpless_sys::*;
-
 use std::error::Error;
-use std::ffi::CString;
+use std::io::{self, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use crate::format::format_entry;
+use crate::retry::{is_closed, RetryPolicy};
+use crate::server::with_default_port;
+use crate::{Entry, Format, Target};
 
-use crate::StumplessError;
-use crate::Target;
+pub const DEFAULT_TCP_PORT: u16 = 514;
+
+// the two framing methods of RFC 6587, receivers differ in which they accept
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Framing {
+    // each message is preceded by its length, so it may contain newlines
+    OctetCounting,
+    // each message is followed by a newline, so newlines in it are escaped
+    NonTransparent,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connected,
+    // the next entry will try to connect again
+    Disconnected,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ConnectionStats {
+    pub entries_sent: u64,
+    // entries given up on after every retry failed
+    pub entries_failed: u64,
+    pub reconnects: u64,
+    pub connect_failures: u64,
+    pub write_failures: u64,
+}
 
 pub struct NetworkTarget {
-    target: *mut stumpless_target,
+    server: String,
+    framing: Framing,
+    format: Format,
+    ipv4_only: bool,
+    retry: RetryPolicy,
+    connection: Mutex<Connection>,
+}
+
+struct Connection {
+    stream: Option<TcpStream>,
+    stats: ConnectionStats,
+}
+
+pub struct NetworkTargetBuilder {
+    server: String,
+    framing: Framing,
+    format: Format,
+    ipv4_only: bool,
+    retry: RetryPolicy,
 }
 
 impl NetworkTarget {
+    // a collector on this host at the default port, other servers are given
+    // to the builder
     pub fn new() -> Result<Self, Box<dyn Error>> {
-        let target_name = CString::new("stumpless-cli")?;
-        let network_target = unsafe { stumpless_open_network_target(target_name.as_ptr(), 0, 0) };
+        NetworkTarget::builder("localhost").build()
+    }
 
-        if network_target.is_null() {
-            Err(Box::new(StumplessError))
-        } else {
-            Ok(NetworkTarget {
-                target: network_target,
-            })
+    pub fn builder(server: &str) -> NetworkTargetBuilder {
+        NetworkTargetBuilder {
+            server: server.to_string(),
+            framing: Framing::NonTransparent,
+            format: Format::Rfc5424,
+            ipv4_only: false,
+            retry: RetryPolicy::default(),
         }
     }
+
+    pub fn server(&self) -> &str {
+        &self.server
+    }
+
+    pub fn framing(&self) -> Framing {
+        self.framing
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    pub fn state(&self) -> ConnectionState {
+        match self.connection.lock().unwrap().stream {
+            Some(_) => ConnectionState::Connected,
+            None => ConnectionState::Disconnected,
+        }
+    }
+
+    pub fn stats(&self) -> ConnectionStats {
+        self.connection.lock().unwrap().stats
+    }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        self.retry
+    }
+
+    fn send(&self, message: &str) -> io::Result<usize> {
+        let framed = frame(self.framing, message);
+        let mut attempt = 0;
+
+        loop {
+            {
+                let mut connection = self.connection.lock().unwrap();
+
+                let error = match self.write(&mut connection, framed.as_bytes()) {
+                    Ok(()) => {
+                        connection.stats.entries_sent += 1;
+                        return Ok(framed.len());
+                    }
+                    Err(error) => error,
+                };
+
+                if attempt >= self.retry.max_retries {
+                    connection.stats.entries_failed += 1;
+                    return Err(error);
+                }
+            }
+
+            // the connection is unlocked while waiting, so that other
+            // threads can still check on it or get their entry through
+            thread::sleep(self.retry.backoff(attempt));
+            attempt += 1;
+        }
+    }
+
+    fn write(&self, connection: &mut Connection, framed: &[u8]) -> io::Result<()> {
+        // a collector that went away is usually only noticed on the write
+        // after the one that was lost, so check for it up front
+        if connection.stream.as_ref().map_or(false, is_closed) {
+            connection.stream = None;
+        }
+
+        if connection.stream.is_none() {
+            match self.connect() {
+                Ok(stream) => {
+                    connection.stream = Some(stream);
+                    connection.stats.reconnects += 1;
+                }
+                Err(error) => {
+                    connection.stats.connect_failures += 1;
+                    return Err(error);
+                }
+            }
+        }
+
+        let stream = connection.stream.as_mut().unwrap();
+        if let Err(error) = stream.write_all(framed) {
+            connection.stream = None;
+            connection.stats.write_failures += 1;
+            return Err(error);
+        }
+
+        Ok(())
+    }
+
+    fn connect(&self) -> io::Result<TcpStream> {
+        let addresses = self.server.to_socket_addrs()?;
+        let ipv4_only = self.ipv4_only;
+
+        self.retry.connect(
+            addresses.filter(|address| !ipv4_only || address.is_ipv4()),
+            &self.server,
+        )
+    }
+}
+
+impl NetworkTargetBuilder {
+    pub fn framing(mut self, framing: Framing) -> Self {
+        self.framing = framing;
+        self
+    }
+
+    pub fn format(mut self, format: Format) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn ipv4_only(mut self, ipv4_only: bool) -> Self {
+        self.ipv4_only = ipv4_only;
+        self
+    }
+
+    pub fn retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.retry.max_retries = max_retries;
+        self
+    }
+
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.retry.initial_backoff = initial;
+        self.retry.max_backoff = max;
+        self
+    }
+
+    pub fn connect_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.retry.connect_timeout = timeout;
+        self
+    }
+
+    pub fn write_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.retry.write_timeout = timeout;
+        self
+    }
+
+    pub fn build(self) -> Result<NetworkTarget, Box<dyn Error>> {
+        let mut target = NetworkTarget {
+            server: with_default_port(&self.server, DEFAULT_TCP_PORT),
+            framing: self.framing,
+            format: self.format,
+            ipv4_only: self.ipv4_only,
+            retry: self.retry,
+            connection: Mutex::new(Connection {
+                stream: None,
+                stats: ConnectionStats::default(),
+            }),
+        };
+
+        let stream = target.connect()?;
+        target.connection.get_mut().unwrap().stream = Some(stream);
+
+        Ok(target)
+    }
 }
 
 impl Target for NetworkTarget {
-    fn get_pointer(&self) -> *mut stumpless_target {
-        self.target
+    fn log(&self, entry: &Entry) -> Result<usize, Box<dyn Error>> {
+        Ok(self.send(&format_entry(self.format, entry)?)?)
+    }
+}
+
+pub(crate) fn frame(framing: Framing, message: &str) -> String {
+    match framing {
+        Framing::OctetCounting => format!("{} {}", message.len(), message),
+        // the same escapes rsyslog uses for control characters
+        Framing::NonTransparent => {
+            format!("{}\n", message.replace('\n', "#012").replace('\r', "#015"))
+        }
     }
 }
 
-impl Drop for NetworkTarget {
-    fn drop(&mut self) {
-        unsafe {
-            stumpless_close_network_target(self.target);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Facility, Severity};
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::thread::JoinHandle;
+
+    // accepts a single connection and returns everything read from it until
+    // the target closes it
+    fn listen() -> (String, JoinHandle<Vec<u8>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = listener.local_addr().unwrap().to_string();
+
+        let received = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut received = Vec::new();
+            stream.read_to_end(&mut received).unwrap();
+            received
+        });
+
+        (server, received)
+    }
+
+    fn send_with(framing: Framing, messages: &[&str]) -> Vec<u8> {
+        let (server, received) = listen();
+        let target = NetworkTarget::builder(&server)
+            .framing(framing)
+            .build()
+            .unwrap();
+
+        for message in messages {
+            target.send(message).unwrap();
         }
+        drop(target);
+
+        received.join().unwrap()
+    }
+
+    #[test]
+    fn frames_with_octet_counting() {
+        let received = send_with(
+            Framing::OctetCounting,
+            &["<14>1 - - - - - first", "<14>1 - - - - - two\nlines"],
+        );
+
+        assert_eq!(
+            received,
+            b"21 <14>1 - - - - - first25 <14>1 - - - - - two\nlines"
+        );
+    }
+
+    #[test]
+    fn frames_with_trailing_newlines() {
+        let received = send_with(
+            Framing::NonTransparent,
+            &["<14>1 - - - - - first", "<14>1 - - - - - two\nlines\r"],
+        );
+
+        assert_eq!(
+            received,
+            b"<14>1 - - - - - first\n<14>1 - - - - - two#012lines#015\n"
+        );
+    }
+
+    #[test]
+    fn logs_entries_in_the_chosen_format() {
+        let (server, received) = listen();
+        let target = NetworkTarget::builder(&server)
+            .framing(Framing::OctetCounting)
+            .build()
+            .unwrap();
+        let entry = Entry::new(Facility::User, Severity::Info, "app", "msgid", "hello").unwrap();
+
+        let sent = target.log(&entry).unwrap();
+        drop(target);
+
+        let received = String::from_utf8(received.join().unwrap()).unwrap();
+        let (length, message) = received.split_once(' ').unwrap();
+        assert_eq!(sent, received.len());
+        assert_eq!(length.parse::<usize>().unwrap(), message.len());
+        assert!(message.starts_with("<14>1 "));
+        assert!(message.ends_with(" msgid - hello"));
+    }
+
+    #[test]
+    fn reconnects_to_a_restarted_collector() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = listener.local_addr().unwrap().to_string();
+        let target = NetworkTarget::builder(&server)
+            .framing(Framing::OctetCounting)
+            .build()
+            .unwrap();
+
+        // the collector reads the first entry and then goes away
+        target.send("before").unwrap();
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut first = [0u8; 8];
+        stream.read_exact(&mut first).unwrap();
+        assert_eq!(&first, b"6 before");
+        drop(stream);
+        drop(listener);
+
+        let listener = TcpListener::bind(&server).unwrap();
+        let received = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut received = Vec::new();
+            stream.read_to_end(&mut received).unwrap();
+            received
+        });
+
+        target.send("after").unwrap();
+        let stats = target.stats();
+        drop(target);
+
+        assert_eq!(received.join().unwrap(), b"5 after");
+        assert_eq!(stats.entries_sent, 2);
+        assert_eq!(stats.reconnects, 1);
+        assert_eq!(stats.entries_failed, 0);
+    }
+
+    #[test]
+    fn gives_up_after_the_last_retry() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = listener.local_addr().unwrap().to_string();
+        let target = NetworkTarget::builder(&server)
+            .max_retries(2)
+            .backoff(Duration::from_millis(1), Duration::from_millis(1))
+            .build()
+            .unwrap();
+        drop(listener);
+        drop(target.connection.lock().unwrap().stream.take());
+
+        assert!(target.send("lost").is_err());
+
+        let stats = target.stats();
+        assert_eq!(target.state(), ConnectionState::Disconnected);
+        assert_eq!(stats.connect_failures, 3);
+        assert_eq!(stats.entries_failed, 1);
+    }
+
+    #[test]
+    fn stays_unlocked_during_backoff() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = listener.local_addr().unwrap().to_string();
+        let target = std::sync::Arc::new(
+            NetworkTarget::builder(&server)
+                .max_retries(1)
+                .backoff(Duration::from_secs(1), Duration::from_secs(1))
+                .build()
+                .unwrap(),
+        );
+        drop(listener);
+        drop(target.connection.lock().unwrap().stream.take());
+
+        let sender = std::sync::Arc::clone(&target);
+        let sending = thread::spawn(move || sender.send("lost").is_err());
+        thread::sleep(Duration::from_millis(100));
+
+        let started = std::time::Instant::now();
+        assert_eq!(target.state(), ConnectionState::Disconnected);
+        assert_eq!(target.stats().connect_failures, 1);
+        assert!(started.elapsed() < Duration::from_millis(100));
+
+        assert!(sending.join().unwrap());
     }
 }
@@ -0,0 +1,402 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crate::{Entry, Facility, Severity, Target};
+
+// buckets that are full and have nothing suppressed are forgotten once there
+// are more keys than this, so a stream of unique msgids can't grow the map
+const MAX_IDLE_BUCKETS: usize = 1024;
+
+// what entries are grouped by when deciding whether they are over the limit
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RateLimitKey {
+    // a single limit for everything logged to the target
+    Target,
+    AppName,
+    Msgid,
+    Severity,
+}
+
+// drops entries beyond a token bucket limit, and once entries are let through
+// again logs a summary of how many were suppressed
+pub struct RateLimitedTarget<T: Target> {
+    inner: T,
+    rate: f64,
+    burst: f64,
+    key: RateLimitKey,
+    buckets: Mutex<Buckets>,
+}
+
+pub struct RateLimitedTargetBuilder<T: Target> {
+    inner: T,
+    rate: f64,
+    burst: Option<u32>,
+    key: RateLimitKey,
+}
+
+struct Buckets {
+    map: HashMap<String, Bucket>,
+    // idle buckets are swept once the map grows past this, which doubles
+    // with the buckets left after each sweep so that it happens less often
+    // the more keys are busy
+    sweep_at: usize,
+}
+
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+    suppressed: Option<Suppressed>,
+}
+
+// the facility and app name of the last suppressed entry are used for the
+// summary, so that it is filed alongside the entries it stands in for
+struct Suppressed {
+    count: u64,
+    since: Instant,
+    facility: Facility,
+    app_name: String,
+}
+
+impl<T: Target> RateLimitedTarget<T> {
+    pub fn new(inner: T, rate: f64) -> Self {
+        RateLimitedTarget::builder(inner, rate).build()
+    }
+
+    // the rate is the number of entries let through per second, on average
+    pub fn builder(inner: T, rate: f64) -> RateLimitedTargetBuilder<T> {
+        RateLimitedTargetBuilder {
+            inner,
+            rate,
+            burst: None,
+            key: RateLimitKey::Target,
+        }
+    }
+
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    // entries dropped since the last summary was logged, across all keys
+    pub fn suppressed(&self) -> u64 {
+        let buckets = self.buckets.lock().unwrap();
+        buckets
+            .map
+            .values()
+            .filter_map(|bucket| bucket.suppressed.as_ref())
+            .map(|suppressed| suppressed.count)
+            .sum()
+    }
+
+    // logs a summary for every key with suppressed entries, without waiting
+    // for another entry to be let through
+    pub fn flush(&self) -> Result<(), Box<dyn Error>> {
+        let mut buckets = self.buckets.lock().unwrap();
+        let mut result = Ok(());
+
+        for (key, bucket) in buckets.map.iter_mut() {
+            if let Some(suppressed) = bucket.suppressed.take() {
+                if let Err(error) = self.log_summary(key, &suppressed) {
+                    bucket.suppressed = Some(suppressed);
+                    result = Err(error);
+                }
+            }
+        }
+
+        result
+    }
+
+    fn key_of(&self, entry: &Entry) -> Result<String, Box<dyn Error>> {
+        match self.key {
+            RateLimitKey::Target => Ok(String::new()),
+            RateLimitKey::AppName => entry.get_app_name(),
+            RateLimitKey::Msgid => entry.get_msgid(),
+            RateLimitKey::Severity => Ok(severity_name(entry.get_severity()?)),
+        }
+    }
+
+    fn log_summary(&self, key: &str, suppressed: &Suppressed) -> Result<usize, Box<dyn Error>> {
+        let source = match self.key {
+            RateLimitKey::Target => String::new(),
+            RateLimitKey::AppName => format!(" from app {}", key),
+            RateLimitKey::Msgid => format!(" with msgid {}", key),
+            RateLimitKey::Severity => format!(" at severity {}", key),
+        };
+        let message = format!(
+            "suppressed {} messages{} in the last {}s",
+            suppressed.count,
+            source,
+            suppressed.since.elapsed().as_secs().max(1)
+        );
+
+        let summary = Entry::new(
+            suppressed.facility,
+            Severity::Warning,
+            &suppressed.app_name,
+            "-",
+            &message,
+        )?;
+        self.inner.log(&summary)
+    }
+}
+
+impl<T: Target> RateLimitedTargetBuilder<T> {
+    // how many entries can be let through at once after a quiet period,
+    // which defaults to one second's worth
+    pub fn burst(mut self, burst: u32) -> Self {
+        self.burst = Some(burst);
+        self
+    }
+
+    pub fn key(mut self, key: RateLimitKey) -> Self {
+        self.key = key;
+        self
+    }
+
+    pub fn build(self) -> RateLimitedTarget<T> {
+        let burst = match self.burst {
+            Some(burst) => burst as f64,
+            None => self.rate.ceil(),
+        };
+
+        RateLimitedTarget {
+            inner: self.inner,
+            rate: self.rate.max(0.0),
+            burst: burst.max(1.0),
+            key: self.key,
+            buckets: Mutex::new(Buckets {
+                map: HashMap::new(),
+                sweep_at: MAX_IDLE_BUCKETS,
+            }),
+        }
+    }
+}
+
+impl<T: Target> Target for RateLimitedTarget<T> {
+    // suppressed entries are not written, and so return a length of zero
+    fn log(&self, entry: &Entry) -> Result<usize, Box<dyn Error>> {
+        let key = self.key_of(entry)?;
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+
+        if buckets.map.len() > buckets.sweep_at {
+            let (rate, burst) = (self.rate, self.burst);
+            buckets.map.retain(|_, bucket| {
+                bucket.refill(now, rate, burst);
+                bucket.suppressed.is_some() || bucket.tokens < burst
+            });
+            buckets.sweep_at = (buckets.map.len() * 2).max(MAX_IDLE_BUCKETS);
+        }
+
+        let bucket = buckets.map.entry(key.clone()).or_insert(Bucket {
+            tokens: self.burst,
+            last_refill: now,
+            suppressed: None,
+        });
+        bucket.refill(now, self.rate, self.burst);
+
+        if bucket.tokens < 1.0 {
+            match &mut bucket.suppressed {
+                Some(suppressed) => {
+                    suppressed.count += 1;
+                    suppressed.facility = entry.get_facility()?;
+                    suppressed.app_name = entry.get_app_name()?;
+                }
+                None => {
+                    bucket.suppressed = Some(Suppressed {
+                        count: 1,
+                        since: now,
+                        facility: entry.get_facility()?,
+                        app_name: entry.get_app_name()?,
+                    });
+                }
+            }
+
+            return Ok(0);
+        }
+
+        bucket.tokens -= 1.0;
+
+        // the summary doesn't count against the limit, as it replaces the
+        // entries that were dropped, and one that fails is kept for the next
+        // entry let through rather than holding this one back
+        if let Some(suppressed) = bucket.suppressed.take() {
+            if self.log_summary(&key, &suppressed).is_err() {
+                bucket.suppressed = Some(suppressed);
+            }
+        }
+
+        self.inner.log(entry)
+    }
+}
+
+impl<T: Target> Drop for RateLimitedTarget<T> {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+impl Bucket {
+    fn refill(&mut self, now: Instant, rate: f64, burst: f64) {
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(burst);
+        self.last_refill = now;
+    }
+}
+
+fn severity_name(severity: Severity) -> String {
+    format!("{:?}", severity).to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    // records the severity, app name and message of each entry, failing for
+    // summaries if asked to
+    #[derive(Clone, Default)]
+    struct Recording {
+        entries: Arc<Mutex<Vec<(Severity, String, String)>>>,
+        fail_summaries: bool,
+    }
+
+    impl Recording {
+        fn messages(&self) -> Vec<String> {
+            let entries = self.entries.lock().unwrap();
+            entries
+                .iter()
+                .map(|(_, _, message)| message.clone())
+                .collect()
+        }
+    }
+
+    impl Target for Recording {
+        fn log(&self, entry: &Entry) -> Result<usize, Box<dyn Error>> {
+            let message = entry.get_message()?;
+            if self.fail_summaries && message.starts_with("suppressed") {
+                return Err(Box::new(crate::StumplessError));
+            }
+
+            let length = message.len();
+            self.entries.lock().unwrap().push((
+                entry.get_severity()?,
+                entry.get_app_name()?,
+                message,
+            ));
+            Ok(length)
+        }
+    }
+
+    fn log(target: &RateLimitedTarget<Recording>, msgid: &str, message: &str) {
+        let entry = Entry::new(Facility::Local0, Severity::Info, "app", msgid, message).unwrap();
+        target.log(&entry).unwrap();
+    }
+
+    #[test]
+    fn suppresses_entries_over_the_burst() {
+        let inner = Recording::default();
+        let target = RateLimitedTarget::builder(inner.clone(), 0.001)
+            .burst(2)
+            .build();
+
+        for i in 0..5 {
+            log(&target, "msgid", &format!("entry {}", i));
+        }
+
+        assert_eq!(inner.messages(), ["entry 0", "entry 1"]);
+        assert_eq!(target.suppressed(), 3);
+    }
+
+    #[test]
+    fn refills_and_logs_a_summary_first() {
+        let inner = Recording::default();
+        let target = RateLimitedTarget::builder(inner.clone(), 20.0)
+            .burst(1)
+            .build();
+
+        log(&target, "msgid", "first");
+        log(&target, "msgid", "dropped");
+        log(&target, "msgid", "dropped");
+        thread::sleep(Duration::from_millis(100));
+        log(&target, "msgid", "second");
+
+        assert_eq!(
+            inner.messages(),
+            ["first", "suppressed 2 messages in the last 1s", "second"]
+        );
+        assert_eq!(target.suppressed(), 0);
+
+        let entries = inner.entries.lock().unwrap();
+        assert_eq!(entries[1].0, Severity::Warning);
+        assert_eq!(entries[1].1, "app");
+    }
+
+    #[test]
+    fn keeps_a_bucket_per_key() {
+        let inner = Recording::default();
+        let target = RateLimitedTarget::builder(inner.clone(), 0.001)
+            .burst(1)
+            .key(RateLimitKey::Msgid)
+            .build();
+
+        log(&target, "a", "a first");
+        log(&target, "b", "b first");
+        log(&target, "a", "a second");
+        log(&target, "b", "b second");
+        log(&target, "b", "b third");
+        target.flush().unwrap();
+
+        let mut messages = inner.messages();
+        messages[2..].sort();
+        assert_eq!(
+            messages,
+            [
+                "a first",
+                "b first",
+                "suppressed 1 messages with msgid a in the last 1s",
+                "suppressed 2 messages with msgid b in the last 1s",
+            ]
+        );
+    }
+
+    #[test]
+    fn forwards_the_entry_when_the_summary_fails() {
+        let inner = Recording {
+            fail_summaries: true,
+            ..Recording::default()
+        };
+        let target = RateLimitedTarget::builder(inner.clone(), 20.0)
+            .burst(1)
+            .build();
+
+        log(&target, "msgid", "first");
+        log(&target, "msgid", "dropped");
+        thread::sleep(Duration::from_millis(100));
+        log(&target, "msgid", "second");
+
+        assert_eq!(inner.messages(), ["first", "second"]);
+        assert_eq!(target.suppressed(), 1);
+        assert!(target.flush().is_err());
+        assert_eq!(target.suppressed(), 1);
+    }
+
+    #[test]
+    fn forgets_idle_buckets() {
+        let inner = Recording::default();
+        let target = RateLimitedTarget::builder(inner.clone(), 1_000_000_000.0)
+            .key(RateLimitKey::Msgid)
+            .build();
+
+        for i in 0..MAX_IDLE_BUCKETS * 4 {
+            log(&target, &format!("msgid-{}", i), "entry");
+        }
+
+        let buckets = target.buckets.lock().unwrap();
+        assert!(buckets.map.len() <= MAX_IDLE_BUCKETS + 1);
+        assert_eq!(buckets.sweep_at, MAX_IDLE_BUCKETS);
+    }
+}
@@ -0,0 +1,125 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+// how targets with a connection to a collector retry entries and reconnect
+// once the connection is lost
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    // the number of times an entry is retried after the first attempt fails
+    pub max_retries: u32,
+    // the wait before a retry doubles each time from the initial backoff up
+    // to the maximum
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub connect_timeout: Option<Duration>,
+    // only targets that wait on the collector, such as for a TLS handshake,
+    // read from the connection
+    pub read_timeout: Option<Duration>,
+    pub write_timeout: Option<Duration>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+            connect_timeout: Some(Duration::from_secs(10)),
+            read_timeout: Some(Duration::from_secs(10)),
+            write_timeout: Some(Duration::from_secs(10)),
+        }
+    }
+}
+
+impl RetryPolicy {
+    // somewhere between half and all of the exponential delay, so that many
+    // clients don't reconnect to a restarted collector in lockstep
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let delay = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_backoff);
+        let random = RandomState::new().build_hasher().finish();
+
+        delay / 2 + delay.mul_f64((random % 1000) as f64 / 2000.0)
+    }
+
+    // tries each address in turn, within the connect timeout
+    pub(crate) fn connect(
+        &self,
+        addresses: impl Iterator<Item = SocketAddr>,
+        server: &str,
+    ) -> io::Result<TcpStream> {
+        let mut last_error = io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no usable address found for {}", server),
+        );
+
+        for address in addresses {
+            let stream = match self.connect_timeout {
+                Some(timeout) => TcpStream::connect_timeout(&address, timeout),
+                None => TcpStream::connect(address),
+            };
+
+            match stream {
+                Ok(stream) => {
+                    stream.set_read_timeout(self.read_timeout)?;
+                    stream.set_write_timeout(self.write_timeout)?;
+                    return Ok(stream);
+                }
+                Err(error) => last_error = error,
+            }
+        }
+
+        Err(last_error)
+    }
+}
+
+// collectors never send anything back, so a readable socket means the
+// connection was closed or reset
+#[cfg(any(feature = "gelf", feature = "network"))]
+pub(crate) fn is_closed(stream: &TcpStream) -> bool {
+    if stream.set_nonblocking(true).is_err() {
+        return true;
+    }
+
+    let closed = match stream.peek(&mut [0u8; 1]) {
+        Ok(0) => true,
+        Ok(_) => false,
+        Err(error) => error.kind() != io::ErrorKind::WouldBlock,
+    };
+
+    stream.set_nonblocking(false).is_err() || closed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_up_to_the_maximum() {
+        let policy = RetryPolicy {
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(1000),
+            ..RetryPolicy::default()
+        };
+
+        for (attempt, delay) in [
+            (0, 100),
+            (1, 200),
+            (2, 400),
+            (3, 800),
+            (4, 1000),
+            (40, 1000),
+        ] {
+            let backoff = policy.backoff(attempt);
+            let delay = Duration::from_millis(delay);
+
+            assert!(backoff >= delay / 2, "{:?} at attempt {}", backoff, attempt);
+            assert!(backoff <= delay, "{:?} at attempt {}", backoff, attempt);
+        }
+    }
+}
@@ -0,0 +1,442 @@
+use std::error::Error;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::file::FileOptions;
+use crate::format::format_entry;
+use crate::timestamp::Timestamp;
+use crate::{Entry, FileTarget, Target};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RotationInterval {
+    Hourly,
+    Daily,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RotationNaming {
+    // file.log.1 is the most recent rotated file
+    Index,
+    // file.log.2022-07-30 or file.log.2022-07-30-13 for hourly rotation
+    Date,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct RotationPolicy {
+    pub max_size: Option<u64>,
+    pub interval: Option<RotationInterval>,
+    pub keep: usize,
+    pub naming: RotationNaming,
+    pub compress: bool,
+}
+
+pub struct RotatingFileTarget {
+    path: PathBuf,
+    options: FileOptions,
+    policy: RotationPolicy,
+    state: Mutex<RotationState>,
+}
+
+struct RotationState {
+    target: Option<FileTarget>,
+    period: String,
+}
+
+impl RotatingFileTarget {
+    pub(crate) fn open(
+        filename: &str,
+        options: FileOptions,
+        policy: RotationPolicy,
+    ) -> Result<Self, Box<dyn Error>> {
+        // without either, the file would never be rotated and keep would
+        // have no effect
+        if policy.max_size.is_none() && policy.interval.is_none() {
+            return Err(Box::new(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "a rotating file target needs a maximum size or a rotation interval",
+            )));
+        }
+
+        let path = PathBuf::from(filename);
+        let target = FileTarget::open(filename, options.clone())?;
+        let period = current_period(&policy);
+
+        Ok(RotatingFileTarget {
+            path,
+            options,
+            policy,
+            state: Mutex::new(RotationState {
+                target: Some(target),
+                period,
+            }),
+        })
+    }
+
+    pub fn rotate(&self) -> Result<(), Box<dyn Error>> {
+        let mut state = self.state.lock().unwrap();
+        self.rotate_locked(&mut state)
+    }
+
+    fn open_target(&self) -> Result<FileTarget, Box<dyn Error>> {
+        FileTarget::open(&self.path.to_string_lossy(), self.options.clone())
+    }
+
+    fn needs_rotation(&self, state: &RotationState, entry: &Entry) -> Result<bool, Box<dyn Error>> {
+        if let Some(max_size) = self.policy.max_size {
+            let current = fs::metadata(&self.path).map(|m| m.len()).unwrap_or(0);
+            // the line as it will be written, with its newline
+            let incoming = format_entry(self.options.format, entry)?.len() as u64 + 1;
+
+            // an entry larger than max_size still gets a file of its own
+            if current > 0 && current + incoming > max_size {
+                return Ok(true);
+            }
+        }
+
+        Ok(self.policy.interval.is_some() && current_period(&self.policy) != state.period)
+    }
+
+    fn rotate_locked(&self, state: &mut RotationState) -> Result<(), Box<dyn Error>> {
+        // close the current file first so that rotation also works on Windows
+        state.target.take();
+
+        let rotated = match self.policy.naming {
+            RotationNaming::Index => self.rotate_indexed(),
+            RotationNaming::Date => self.rotate_dated(&state.period),
+        };
+
+        state.period = current_period(&self.policy);
+        state.target = Some(self.open_target()?);
+
+        if let Some(rotated) = rotated? {
+            if self.policy.compress {
+                compress(&rotated)?;
+            }
+        }
+
+        if self.policy.naming == RotationNaming::Date {
+            self.prune_dated()?;
+        }
+
+        Ok(())
+    }
+
+    fn rotate_indexed(&self) -> io::Result<Option<PathBuf>> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+
+        if self.policy.keep == 0 {
+            remove_if_exists(&self.path)?;
+            return Ok(None);
+        }
+
+        remove_if_exists(&self.indexed_path(self.policy.keep))?;
+        for index in (1..self.policy.keep).rev() {
+            let from = self.indexed_path(index);
+            if from.exists() {
+                fs::rename(&from, self.indexed_path(index + 1))?;
+            }
+        }
+
+        let rotated = with_suffix(&self.path, "1");
+        fs::rename(&self.path, &rotated)?;
+        Ok(Some(rotated))
+    }
+
+    fn indexed_path(&self, index: usize) -> PathBuf {
+        let path = with_suffix(&self.path, &index.to_string());
+
+        if self.policy.compress {
+            with_suffix(&path, "gz")
+        } else {
+            path
+        }
+    }
+
+    fn rotate_dated(&self, period: &str) -> io::Result<Option<PathBuf>> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+
+        if self.policy.keep == 0 {
+            remove_if_exists(&self.path)?;
+            return Ok(None);
+        }
+
+        // size based rotation can happen more than once in a single period
+        let mut rotated = with_suffix(&self.path, period);
+        let mut collision = 0;
+        while rotated.exists() || with_suffix(&rotated, "gz").exists() {
+            collision += 1;
+            rotated = with_suffix(&self.path, &format!("{}.{}", period, collision));
+        }
+
+        fs::rename(&self.path, &rotated)?;
+        Ok(Some(rotated))
+    }
+
+    fn prune_dated(&self) -> io::Result<()> {
+        let directory = match self.path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        let prefix = match self.path.file_name() {
+            Some(name) => format!("{}.", name.to_string_lossy()),
+            None => return Ok(()),
+        };
+
+        let mut rotated_files = Vec::new();
+        for dir_entry in fs::read_dir(&directory)? {
+            let file_name = dir_entry?.file_name().to_string_lossy().into_owned();
+            if let Some(key) = file_name.strip_prefix(&prefix).and_then(dated_sort_key) {
+                rotated_files.push((key, file_name));
+            }
+        }
+
+        rotated_files.sort();
+        let excess = rotated_files.len().saturating_sub(self.policy.keep);
+        for (_, file_name) in &rotated_files[..excess] {
+            fs::remove_file(directory.join(file_name))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Target for RotatingFileTarget {
+    fn log(&self, entry: &Entry) -> Result<usize, Box<dyn Error>> {
+        let mut state = self.state.lock().unwrap();
+
+        if state.target.is_none() {
+            // a previous rotation could not reopen the file
+            state.target = Some(self.open_target()?);
+        } else if self.needs_rotation(&state, entry)? {
+            self.rotate_locked(&mut state)?;
+        }
+
+        state.target.as_ref().unwrap().log(entry)
+    }
+}
+
+fn current_period(policy: &RotationPolicy) -> String {
+    let now = Timestamp::now();
+
+    match policy.interval {
+        Some(RotationInterval::Hourly) => now.date_hour(),
+        _ => now.date(),
+    }
+}
+
+// orders the suffixes of dated files from oldest to newest, which a plain
+// string sort gets wrong once there are ten collisions in a period or some
+// of the files are compressed
+fn dated_sort_key(suffix: &str) -> Option<(Vec<u32>, u32)> {
+    let suffix = suffix.strip_suffix(".gz").unwrap_or(suffix);
+    let (period, collision) = match suffix.split_once('.') {
+        Some((period, collision)) => (period, collision.parse().ok()?),
+        None => (suffix, 0),
+    };
+
+    let period = period
+        .split('-')
+        .map(|part| part.parse().ok())
+        .collect::<Option<Vec<u32>>>()?;
+    if !(3..=4).contains(&period.len()) {
+        return None;
+    }
+
+    Some((period, collision))
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(".");
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(feature = "gzip")]
+fn compress(path: &Path) -> io::Result<()> {
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    let mut input = fs::File::open(path)?;
+    let output = fs::File::create(with_suffix(path, "gz"))?;
+    let mut encoder = GzEncoder::new(output, Compression::default());
+    io::copy(&mut input, &mut encoder)?;
+    encoder.finish()?.sync_all()?;
+
+    fs::remove_file(path)
+}
+
+#[cfg(not(feature = "gzip"))]
+fn compress(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Facility, Format, Severity};
+
+    #[test]
+    fn dated_sort_key_orders_collisions_numerically() {
+        let mut suffixes = vec![
+            "2022-07-30.10",
+            "2022-07-30.gz",
+            "2022-07-30.2",
+            "2022-07-30.1.gz",
+            "2022-07-29-23",
+            "2022-07-30-01",
+        ];
+        suffixes.sort_by_key(|suffix| dated_sort_key(suffix).unwrap());
+
+        assert_eq!(
+            suffixes,
+            [
+                "2022-07-29-23",
+                "2022-07-30.gz",
+                "2022-07-30.1.gz",
+                "2022-07-30.2",
+                "2022-07-30.10",
+                "2022-07-30-01",
+            ]
+        );
+    }
+
+    #[test]
+    fn dated_sort_key_skips_other_files() {
+        assert_eq!(dated_sort_key("1"), None);
+        assert_eq!(dated_sort_key("1.gz"), None);
+        assert_eq!(dated_sort_key("2022-07-30.old"), None);
+        assert_eq!(dated_sort_key("backup"), None);
+    }
+
+    #[test]
+    fn prune_keeps_newest_dated_files() {
+        let directory =
+            std::env::temp_dir().join(format!("stumpless-prune-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("app.log");
+
+        for suffix in [
+            "2022-07-29.gz",
+            "2022-07-30",
+            "2022-07-30.1.gz",
+            "2022-07-30.2",
+            "2022-07-30.10",
+        ] {
+            fs::write(with_suffix(&path, suffix), "").unwrap();
+        }
+
+        let target = FileTarget::builder(&path.to_string_lossy())
+            .max_size(1024)
+            .naming(RotationNaming::Date)
+            .keep(3)
+            .build_rotating()
+            .unwrap();
+        target.rotate().unwrap();
+
+        let mut remaining: Vec<String> = fs::read_dir(&directory)
+            .unwrap()
+            .map(|dir_entry| {
+                dir_entry
+                    .unwrap()
+                    .file_name()
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect();
+        remaining.sort();
+        fs::remove_dir_all(&directory).unwrap();
+
+        let mut expected = vec![
+            String::from("app.log"),
+            format!("app.log.{}", Timestamp::now().date()),
+            String::from("app.log.2022-07-30.10"),
+            String::from("app.log.2022-07-30.2"),
+        ];
+        expected.sort();
+        assert_eq!(remaining, expected);
+    }
+
+    #[test]
+    fn rotates_before_a_line_would_pass_max_size() {
+        let directory =
+            std::env::temp_dir().join(format!("stumpless-max-size-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("app.log");
+
+        let entry = Entry::new(Facility::User, Severity::Info, "app", "-", "message").unwrap();
+        let line_length = format_entry(Format::JsonLines, &entry).unwrap().len() as u64 + 1;
+
+        let target = FileTarget::builder(&path.to_string_lossy())
+            .format(Format::JsonLines)
+            .max_size(line_length * 2 + 1)
+            .keep(5)
+            .build_rotating()
+            .unwrap();
+        for _ in 0..5 {
+            target.log(&entry).unwrap();
+        }
+
+        let sizes: Vec<u64> = [
+            path.clone(),
+            with_suffix(&path, "1"),
+            with_suffix(&path, "2"),
+        ]
+        .iter()
+        .map(|path| fs::metadata(path).unwrap().len())
+        .collect();
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(sizes, [line_length, line_length * 2, line_length * 2]);
+    }
+
+    #[test]
+    fn writes_a_line_larger_than_max_size_to_an_empty_file() {
+        let directory =
+            std::env::temp_dir().join(format!("stumpless-oversized-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("app.log");
+
+        let target = FileTarget::builder(&path.to_string_lossy())
+            .format(Format::JsonLines)
+            .max_size(10)
+            .keep(5)
+            .build_rotating()
+            .unwrap();
+        target.log_message("longer than ten bytes").unwrap();
+        target.log_message("longer than ten bytes").unwrap();
+
+        let rotated = with_suffix(&path, "1").exists();
+        let unexpected = with_suffix(&path, "2").exists();
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert!(rotated);
+        assert!(!unexpected);
+    }
+
+    #[test]
+    fn rotating_target_needs_a_policy() {
+        let path =
+            std::env::temp_dir().join(format!("stumpless-no-policy-{}.log", std::process::id()));
+
+        assert!(FileTarget::builder(&path.to_string_lossy())
+            .keep(3)
+            .build_rotating()
+            .is_err());
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,455 @@
+use std::collections::hash_map::RandomState;
+use std::error::Error;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::{Entry, Severity, Target};
+
+pub type CorrelationKeyFn = Arc<dyn Fn(&Entry) -> Option<String> + Send + Sync>;
+
+// where the key that groups related entries together is found
+#[derive(Clone)]
+pub enum CorrelationKey {
+    Msgid,
+    // a param of a structured data element, given as the element name and
+    // then the param name
+    Param(String, String),
+    Custom(CorrelationKeyFn),
+}
+
+// forwards a fraction of the entries of each severity, for example all
+// errors but only one in a hundred debug entries
+pub struct SamplingTarget<T: Target> {
+    inner: T,
+    rates: [f64; 8],
+    correlation_key: Option<CorrelationKey>,
+    // the element and param names the rate is added as
+    rate_param: Option<(String, String)>,
+    dropped: AtomicU64,
+}
+
+pub struct SamplingTargetBuilder<T: Target> {
+    inner: T,
+    rates: [f64; 8],
+    correlation_key: Option<CorrelationKey>,
+    rate_param: Option<(String, String)>,
+}
+
+impl CorrelationKey {
+    pub fn custom(key_of: impl Fn(&Entry) -> Option<String> + Send + Sync + 'static) -> Self {
+        CorrelationKey::Custom(Arc::new(key_of))
+    }
+}
+
+impl fmt::Debug for CorrelationKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CorrelationKey::Msgid => f.write_str("Msgid"),
+            CorrelationKey::Param(element_name, param_name) => f
+                .debug_tuple("Param")
+                .field(element_name)
+                .field(param_name)
+                .finish(),
+            CorrelationKey::Custom(_) => f.write_str("Custom(..)"),
+        }
+    }
+}
+
+impl<T: Target> SamplingTarget<T> {
+    pub fn builder(inner: T) -> SamplingTargetBuilder<T> {
+        SamplingTargetBuilder {
+            inner,
+            rates: [1.0; 8],
+            correlation_key: None,
+            rate_param: Some((String::from("sampling"), String::from("rate"))),
+        }
+    }
+
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn rate(&self, severity: Severity) -> f64 {
+        self.rates[severity as usize]
+    }
+
+    // entries that were sampled out
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    // a number in [0, 1) that decides whether an entry is kept
+    fn sample_point(&self, entry: &Entry) -> Result<f64, Box<dyn Error>> {
+        let key = match &self.correlation_key {
+            None => None,
+            // a nil msgid would otherwise group every entry without one
+            Some(CorrelationKey::Msgid) => {
+                Some(entry.get_msgid()?).filter(|msgid| !msgid.is_empty() && msgid != "-")
+            }
+            Some(CorrelationKey::Param(element_name, param_name)) => entry
+                .get_elements()?
+                .into_iter()
+                .filter(|element| &element.name == element_name)
+                .flat_map(|element| element.params)
+                .find(|(name, _)| name == param_name)
+                .map(|(_, value)| value)
+                .filter(|value| !value.is_empty()),
+            Some(CorrelationKey::Custom(key_of)) => key_of(entry),
+        };
+
+        // entries without a key are sampled on their own
+        let hash = match key {
+            Some(key) => key_hash(key.as_bytes()),
+            None => RandomState::new().build_hasher().finish(),
+        };
+
+        Ok((hash >> 11) as f64 / (1u64 << 53) as f64)
+    }
+}
+
+impl<T: Target> SamplingTargetBuilder<T> {
+    // the fraction of entries of the given severity to forward, from 0 for
+    // none to 1 for all of them, which is the default
+    pub fn rate(mut self, severity: Severity, rate: f64) -> Self {
+        self.rates[severity as usize] = rate;
+        self
+    }
+
+    // entries sharing a key are all kept or all dropped together, so that a
+    // sampled request can still be followed from start to finish
+    pub fn correlation_key(mut self, correlation_key: CorrelationKey) -> Self {
+        self.correlation_key = Some(correlation_key);
+        self
+    }
+
+    // the structured data param that forwarded entries carry their sampling
+    // rate in, which defaults to rate in the sampling element
+    pub fn rate_param(mut self, element: &str, param: &str) -> Self {
+        self.rate_param = Some((element.to_string(), param.to_string()));
+        self
+    }
+
+    // forwards entries as they are, without a sampling rate param
+    pub fn without_rate_param(mut self) -> Self {
+        self.rate_param = None;
+        self
+    }
+
+    // rates outside of 0 to 1 are clamped to it, but a NaN can't be
+    pub fn build(self) -> Result<SamplingTarget<T>, Box<dyn Error>> {
+        if let Some(severity) = self.rates.iter().position(|rate| rate.is_nan()) {
+            return Err(Box::new(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "the sampling rate for severity {} is not a number",
+                    severity
+                ),
+            )));
+        }
+
+        Ok(SamplingTarget {
+            inner: self.inner,
+            rates: self.rates.map(|rate| rate.clamp(0.0, 1.0)),
+            correlation_key: self.correlation_key,
+            rate_param: self.rate_param,
+            dropped: AtomicU64::new(0),
+        })
+    }
+}
+
+impl<T: Target> Target for SamplingTarget<T> {
+    // dropped entries are not written, and so return a length of zero
+    fn log(&self, entry: &Entry) -> Result<usize, Box<dyn Error>> {
+        let rate = self.rate(entry.get_severity()?);
+
+        // the same point is compared against the rate of every severity, so
+        // a correlated group kept at 1% is also kept at 10%
+        if rate < 1.0 && self.sample_point(entry)? >= rate {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return Ok(0);
+        }
+
+        let (element_name, param_name) = match &self.rate_param {
+            Some(rate_param) => rate_param,
+            None => return self.inner.log(entry),
+        };
+
+        // the param goes on a copy, as the entry belongs to the caller, and
+        // replaces one left by an earlier sampling target rather than adding
+        // a second
+        let sampled = entry.try_clone()?;
+        let rate = rate.to_string();
+        match sampled
+            .get_elements()?
+            .into_iter()
+            .find(|element| element.name == *element_name)
+        {
+            Some(element) if element.params.iter().any(|(name, _)| name == param_name) => {
+                sampled.set_param(element_name, param_name, &rate)?;
+            }
+            Some(_) => {
+                sampled.add_param(element_name, param_name, &rate)?;
+            }
+            None => {
+                sampled.add_element(element_name)?;
+                sampled.add_param(element_name, param_name, &rate)?;
+            }
+        }
+
+        self.inner.log(&sampled)
+    }
+}
+
+// a fixed hash, so that the same keys are kept across processes and hosts,
+// with the splitmix64 finalizer mixed in as keys like req-1 and req-2 skew
+// the high bits of plain FNV-1a
+fn key_hash(bytes: &[u8]) -> u64 {
+    let hash = bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+
+    let hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    let hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d049bb133111eb);
+    hash ^ (hash >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Facility;
+    use std::sync::Mutex;
+
+    // the message, first sampling rate param and number of those params of
+    // an entry forwarded
+    type Forwarded = (String, Option<String>, usize);
+
+    #[derive(Clone, Default)]
+    struct Recording(Arc<Mutex<Vec<Forwarded>>>);
+
+    impl Recording {
+        fn messages(&self) -> Vec<String> {
+            let entries = self.0.lock().unwrap();
+            entries
+                .iter()
+                .map(|(message, _, _)| message.clone())
+                .collect()
+        }
+    }
+
+    impl Target for Recording {
+        fn log(&self, entry: &Entry) -> Result<usize, Box<dyn Error>> {
+            let rates: Vec<String> = entry
+                .get_elements()?
+                .into_iter()
+                .flat_map(|element| {
+                    let element_name = element.name;
+                    element
+                        .params
+                        .into_iter()
+                        .map(move |(name, value)| (format!("{} {}", element_name, name), value))
+                })
+                .filter(|(name, _)| name == "sampling rate" || name == "meta sr")
+                .map(|(_, value)| value)
+                .collect();
+
+            self.0.lock().unwrap().push((
+                entry.get_message()?,
+                rates.first().cloned(),
+                rates.len(),
+            ));
+            Ok(0)
+        }
+    }
+
+    fn entry(severity: Severity, msgid: &str, message: &str) -> Entry {
+        Entry::new(Facility::User, severity, "app", msgid, message).unwrap()
+    }
+
+    #[test]
+    fn samples_each_severity_at_its_own_rate() {
+        let recording = Recording::default();
+        let target = SamplingTarget::builder(recording.clone())
+            .rate(Severity::Debug, 0.0)
+            .rate(Severity::Info, 0.5)
+            .build()
+            .unwrap();
+
+        for i in 0..1000 {
+            target
+                .log(&entry(Severity::Debug, "-", &format!("debug {}", i)))
+                .unwrap();
+            target
+                .log(&entry(Severity::Info, "-", &format!("info {}", i)))
+                .unwrap();
+            target
+                .log(&entry(Severity::Error, "-", &format!("error {}", i)))
+                .unwrap();
+        }
+
+        let messages = recording.messages();
+        let count = |prefix: &str| messages.iter().filter(|m| m.starts_with(prefix)).count();
+        assert_eq!(count("debug "), 0);
+        assert_eq!(count("error "), 1000);
+        assert!((350..650).contains(&count("info ")), "{}", count("info "));
+        assert_eq!(target.dropped(), 2000 - count("info ") as u64);
+    }
+
+    #[test]
+    fn keeps_or_drops_correlated_entries_together() {
+        let recording = Recording::default();
+        let target = SamplingTarget::builder(recording.clone())
+            .rate(Severity::Info, 0.5)
+            .rate(Severity::Debug, 0.1)
+            .correlation_key(CorrelationKey::Msgid)
+            .build()
+            .unwrap();
+
+        for request in 0..100 {
+            let msgid = format!("req-{}", request);
+            target.log(&entry(Severity::Debug, &msgid, &msgid)).unwrap();
+            for _ in 0..4 {
+                target.log(&entry(Severity::Info, &msgid, &msgid)).unwrap();
+            }
+        }
+
+        let messages = recording.messages();
+        let mut kept = 0;
+        for request in 0..100 {
+            let msgid = format!("req-{}", request);
+            let logged = messages.iter().filter(|&m| *m == msgid).count();
+
+            // a debug entry kept at 10% means the info ones at 50% were too
+            assert!(matches!(logged, 0 | 4 | 5), "{} logged {}", msgid, logged);
+            kept += usize::from(logged > 0);
+        }
+        assert!((20..80).contains(&kept), "{}", kept);
+    }
+
+    #[test]
+    fn samples_entries_without_a_key_on_their_own() {
+        let recording = Recording::default();
+        let target = SamplingTarget::builder(recording.clone())
+            .rate(Severity::Info, 0.5)
+            .correlation_key(CorrelationKey::Msgid)
+            .build()
+            .unwrap();
+
+        for i in 0..200 {
+            target
+                .log(&entry(Severity::Info, "-", &i.to_string()))
+                .unwrap();
+        }
+
+        let kept = recording.messages().len();
+        assert!(kept > 0 && kept < 200, "{}", kept);
+    }
+
+    #[test]
+    fn adds_the_rate_to_forwarded_entries() {
+        let recording = Recording::default();
+        let target = SamplingTarget::builder(recording.clone())
+            .rate(Severity::Info, 0.25)
+            .correlation_key(CorrelationKey::custom(|_| Some(String::from("kept"))))
+            .build()
+            .unwrap();
+        let custom = SamplingTarget::builder(recording.clone())
+            .rate_param("meta", "sr")
+            .build()
+            .unwrap();
+
+        // whether the fixed key is kept at 25% doesn't change between runs
+        let kept = key_hash(b"kept") >> 11 < (1u64 << 51);
+        target.log(&entry(Severity::Info, "-", "sampled")).unwrap();
+        custom
+            .log(&entry(Severity::Info, "-", "unsampled"))
+            .unwrap();
+
+        let mut expected = Vec::new();
+        if kept {
+            expected.push((String::from("sampled"), Some(String::from("0.25")), 1));
+        }
+        expected.push((String::from("unsampled"), Some(String::from("1")), 1));
+        assert_eq!(*recording.0.lock().unwrap(), expected);
+    }
+
+    #[test]
+    fn rejects_a_rate_that_is_not_a_number() {
+        let target = SamplingTarget::builder(Recording::default())
+            .rate(Severity::Info, f64::NAN)
+            .build();
+
+        assert!(target.is_err());
+    }
+
+    #[test]
+    fn replaces_a_rate_param_left_by_an_earlier_target() {
+        let recording = Recording::default();
+        let inner = SamplingTarget::builder(recording.clone()).build().unwrap();
+        let target = SamplingTarget::builder(inner).build().unwrap();
+
+        target.log(&entry(Severity::Info, "-", "twice")).unwrap();
+
+        let forwarded = recording.0.lock().unwrap();
+        assert_eq!(forwarded[0].1.as_deref(), Some("1"));
+        assert_eq!(forwarded[0].2, 1);
+    }
+
+    #[test]
+    fn forwards_entries_as_they_are_without_a_rate_param() {
+        let recording = Recording::default();
+        let target = SamplingTarget::builder(recording.clone())
+            .without_rate_param()
+            .build()
+            .unwrap();
+
+        target.log(&entry(Severity::Info, "-", "plain")).unwrap();
+
+        assert_eq!(
+            *recording.0.lock().unwrap(),
+            [(String::from("plain"), None, 0)]
+        );
+    }
+
+    #[test]
+    fn takes_a_closure_capturing_the_key_param() {
+        let recording = Recording::default();
+        let param_name = String::from("trace");
+        let target = SamplingTarget::builder(recording.clone())
+            .rate(Severity::Info, 0.5)
+            .correlation_key(CorrelationKey::custom(move |entry| {
+                entry
+                    .get_elements()
+                    .ok()?
+                    .into_iter()
+                    .flat_map(|element| element.params)
+                    .find(|(name, _)| *name == param_name)
+                    .map(|(_, value)| value)
+            }))
+            .build()
+            .unwrap();
+
+        for trace in 0..50 {
+            for _ in 0..3 {
+                let traced = entry(Severity::Info, "-", &trace.to_string());
+                traced.add_element("ctx").unwrap();
+                traced
+                    .add_param("ctx", "trace", &trace.to_string())
+                    .unwrap();
+                target.log(&traced).unwrap();
+            }
+        }
+
+        let messages = recording.messages();
+        for trace in 0..50 {
+            let logged = messages.iter().filter(|&m| *m == trace.to_string()).count();
+            assert!(logged == 0 || logged == 3, "{} logged {}", trace, logged);
+        }
+        assert_eq!(
+            format!("{:?}", CorrelationKey::custom(|_| None)),
+            "Custom(..)"
+        );
+    }
+}
@@ -0,0 +1,83 @@
+#[cfg(any(feature = "gelf", feature = "tokio"))]
+use std::{io, net::SocketAddr};
+
+// servers are given as a host name or address, optionally followed by a
+// port, with IPv6 addresses in brackets when a port is given
+
+// appends the port to servers given without one, bracketing bare IPv6
+// addresses so that the result can be resolved
+pub(crate) fn with_default_port(server: &str, port: u16) -> String {
+    // a bare IPv6 address has colons of its own, but no closing bracket
+    let has_port = match server.rsplit_once(':') {
+        Some((host, port)) => {
+            (!host.contains(':') || host.ends_with(']')) && port.parse::<u16>().is_ok()
+        }
+        None => false,
+    };
+
+    if has_port {
+        server.to_string()
+    } else if server.contains(':') && !server.starts_with('[') {
+        format!("[{}]:{}", server, port)
+    } else {
+        format!("{}:{}", server, port)
+    }
+}
+
+// the host of a server that already has a port, without any brackets
+#[cfg(feature = "tls")]
+pub(crate) fn host(server: &str) -> &str {
+    let host = server.rsplit_once(':').map_or(server, |(host, _)| host);
+    host.trim_start_matches('[').trim_end_matches(']')
+}
+
+// the first address a server resolved to, for sockets that can only use one
+#[cfg(any(feature = "gelf", feature = "tokio"))]
+pub(crate) fn first_address(
+    mut addresses: impl Iterator<Item = SocketAddr>,
+    server: &str,
+) -> io::Result<SocketAddr> {
+    addresses.next().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no address found for {}", server),
+        )
+    })
+}
+
+// the unspecified address of the same family as the server, for a local
+// socket to bind to
+#[cfg(any(feature = "gelf", feature = "tokio"))]
+pub(crate) fn unspecified_address(server: &SocketAddr) -> SocketAddr {
+    if server.is_ipv6() {
+        SocketAddr::from(([0u16; 8], 0))
+    } else {
+        SocketAddr::from(([0u8; 4], 0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adds_the_port_when_missing() {
+        assert_eq!(with_default_port("collector", 514), "collector:514");
+        assert_eq!(with_default_port("10.0.0.1", 514), "10.0.0.1:514");
+        assert_eq!(with_default_port("::1", 514), "[::1]:514");
+        assert_eq!(with_default_port("[::1]", 514), "[::1]:514");
+    }
+
+    #[test]
+    fn keeps_a_given_port() {
+        assert_eq!(with_default_port("collector:1514", 514), "collector:1514");
+        assert_eq!(with_default_port("[::1]:1514", 514), "[::1]:1514");
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn finds_the_host() {
+        assert_eq!(host("collector:6514"), "collector");
+        assert_eq!(host("[::1]:6514"), "::1");
+    }
+}
@@ -1,5 +1,8 @@
 use stumpless_sys::*;
 
+use crate::StumplessError;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Severity {
     Emergency = stumpless_severity_STUMPLESS_SEVERITY_EMERG as isize,
     Alert = stumpless_severity_STUMPLESS_SEVERITY_ALERT as isize,
@@ -10,3 +13,21 @@ pub enum Severity {
     Info = stumpless_severity_STUMPLESS_SEVERITY_INFO as isize,
     Debug = stumpless_severity_STUMPLESS_SEVERITY_DEBUG as isize,
 }
+
+impl TryFrom<i32> for Severity {
+    type Error = StumplessError;
+
+    fn try_from(value: i32) -> Result<Self, StumplessError> {
+        match value {
+            value if value == Severity::Emergency as i32 => Ok(Severity::Emergency),
+            value if value == Severity::Alert as i32 => Ok(Severity::Alert),
+            value if value == Severity::Critical as i32 => Ok(Severity::Critical),
+            value if value == Severity::Error as i32 => Ok(Severity::Error),
+            value if value == Severity::Warning as i32 => Ok(Severity::Warning),
+            value if value == Severity::Notice as i32 => Ok(Severity::Notice),
+            value if value == Severity::Info as i32 => Ok(Severity::Info),
+            value if value == Severity::Debug as i32 => Ok(Severity::Debug),
+            _ => Err(StumplessError),
+        }
+    }
+}
@@ -2,40 +2,646 @@ use stumpless_sys::*;
 
 use std::error::Error;
 use std::ffi::CString;
+use std::io::{self, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixDatagram, UnixStream};
+use std::sync::Mutex;
 
-use crate::StumplessError;
-use crate::Target;
+#[cfg(target_os = "linux")]
+use crate::cmsg::send_with_control;
+use crate::format::rfc5424;
+use crate::target::{add_entry_to_pointer, add_message_to_pointer, message_entry, TargetPointer};
+use crate::{Entry, StumplessError, Target};
+
+// the usual places a local syslog daemon listens, in the order they are tried
+pub const DEFAULT_SOCKET_NAMES: [&str; 4] = [
+    "/dev/log",
+    "/run/systemd/journal/syslog",
+    "/var/run/syslog",
+    "/var/run/log",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SocketType {
+    Datagram,
+    Stream,
+}
+
+// the kernel only accepts values other than the caller's own from processes
+// with CAP_SYS_ADMIN, CAP_SETUID or CAP_SETGID respectively
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Credentials {
+    pub pid: u32,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl Credentials {
+    pub fn current() -> Self {
+        Credentials {
+            pid: std::process::id(),
+            uid: unsafe { libc::getuid() },
+            gid: unsafe { libc::getgid() },
+        }
+    }
+
+    // whether the kernel will accept these credentials from this process,
+    // so that unprivileged callers can leave them out rather than have every
+    // send fail with EPERM
+    pub fn permitted(&self) -> bool {
+        let current = Credentials::current();
+        let capabilities = effective_capabilities();
+        let allowed = |own: bool, capability: u32| own || capabilities & (1 << capability) != 0;
+
+        allowed(self.pid == current.pid, CAP_SYS_ADMIN)
+            && allowed(
+                self.uid == current.uid || self.uid == unsafe { libc::geteuid() },
+                CAP_SETUID,
+            )
+            && allowed(
+                self.gid == current.gid || self.gid == unsafe { libc::getegid() },
+                CAP_SETGID,
+            )
+    }
+}
+
+const CAP_SETGID: u32 = 6;
+const CAP_SETUID: u32 = 7;
+const CAP_SYS_ADMIN: u32 = 21;
+
+// the CapEff mask from /proc, which root has every capability in unless
+// they were dropped
+#[cfg(target_os = "linux")]
+fn effective_capabilities() -> u64 {
+    std::fs::read_to_string("/proc/self/status")
+        .ok()
+        .and_then(|status| {
+            status
+                .lines()
+                .find_map(|line| line.strip_prefix("CapEff:"))
+                .and_then(|mask| u64::from_str_radix(mask.trim(), 16).ok())
+        })
+        .unwrap_or(0)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn effective_capabilities() -> u64 {
+    0
+}
 
 pub struct SocketTarget {
-    target: *mut stumpless_target,
+    socket_name: String,
+    credentials: Option<Credentials>,
+    connection: Connection,
+}
+
+enum Connection {
+    Native(TargetPointer),
+    Datagram(UnixDatagram),
+    Stream(Mutex<UnixStream>),
+}
+
+pub struct SocketTargetBuilder {
+    socket_name: String,
+    local_socket: Option<String>,
+    socket_type: SocketType,
+    credentials: Option<Credentials>,
 }
 
 impl SocketTarget {
     pub fn new(socket_name: &str) -> Result<Self, Box<dyn Error>> {
-        let c_socket_name = CString::new(socket_name)?;
-        let socket_target =
-            unsafe { stumpless_open_socket_target(c_socket_name.as_ptr(), std::ptr::null()) };
+        SocketTarget::builder(socket_name).build()
+    }
+
+    pub fn builder(socket_name: &str) -> SocketTargetBuilder {
+        SocketTargetBuilder {
+            socket_name: socket_name.to_string(),
+            local_socket: None,
+            socket_type: SocketType::Datagram,
+            credentials: None,
+        }
+    }
+
+    pub fn discover() -> Result<Self, Box<dyn Error>> {
+        SocketTarget::discover_from(&DEFAULT_SOCKET_NAMES)
+    }
+
+    pub fn discover_from(socket_names: &[&str]) -> Result<Self, Box<dyn Error>> {
+        SocketTarget::builder("").discover_from(socket_names)
+    }
+
+    pub fn socket_name(&self) -> &str {
+        &self.socket_name
+    }
+
+    pub fn socket_type(&self) -> SocketType {
+        match self.connection {
+            Connection::Native(_) | Connection::Datagram(_) => SocketType::Datagram,
+            Connection::Stream(_) => SocketType::Stream,
+        }
+    }
+
+    fn send_datagram(&self, socket: &UnixDatagram, message: &str) -> io::Result<usize> {
+        match &self.credentials {
+            Some(credentials) => send_with_credentials(socket, message.as_bytes(), credentials),
+            None => socket.send(message.as_bytes()),
+        }
+    }
+
+    fn send_stream(&self, stream: &Mutex<UnixStream>, message: &str) -> io::Result<usize> {
+        let mut stream = stream.lock().unwrap();
+        let framed = format!("{}\n", message);
+
+        // the daemon may have restarted since the last entry, so reconnect
+        // once before giving up
+        if self.write_stream(&mut stream, framed.as_bytes()).is_err() {
+            *stream = UnixStream::connect(&self.socket_name)?;
+            self.write_stream(&mut stream, framed.as_bytes())?;
+        }
+
+        Ok(framed.len())
+    }
+
+    // the credentials go with the first write, and after a partial write the
+    // rest of the message is sent on its own until nothing is left
+    fn write_stream(&self, stream: &mut UnixStream, message: &[u8]) -> io::Result<()> {
+        let mut remaining = message;
+
+        if let Some(credentials) = &self.credentials {
+            let sent = loop {
+                match send_with_credentials(stream, remaining, credentials) {
+                    Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                    result => break result?,
+                }
+            };
+            remaining = &remaining[sent..];
+        }
+
+        while !remaining.is_empty() {
+            match stream.write(remaining) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(sent) => remaining = &remaining[sent..],
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+                Err(error) => return Err(error),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl SocketTargetBuilder {
+    // only datagram sockets bind a local socket to receive replies on
+    pub fn local_socket(mut self, local_socket: &str) -> Self {
+        self.local_socket = Some(local_socket.to_string());
+        self
+    }
+
+    pub fn socket_type(mut self, socket_type: SocketType) -> Self {
+        self.socket_type = socket_type;
+        self
+    }
+
+    pub fn credentials(mut self, credentials: Credentials) -> Self {
+        self.credentials = Some(credentials);
+        self
+    }
+
+    pub fn discover(self) -> Result<SocketTarget, Box<dyn Error>> {
+        self.discover_from(&DEFAULT_SOCKET_NAMES)
+    }
+
+    pub fn discover_from(mut self, socket_names: &[&str]) -> Result<SocketTarget, Box<dyn Error>> {
+        for socket_name in socket_names {
+            if let Some(socket_type) = probe(socket_name) {
+                self.socket_name = socket_name.to_string();
+                self.socket_type = socket_type;
+                return self.build();
+            }
+        }
+
+        Err(Box::new(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no syslog socket found, tried {}", socket_names.join(", ")),
+        )))
+    }
+
+    pub fn build(self) -> Result<SocketTarget, Box<dyn Error>> {
+        let connection = match (self.socket_type, self.credentials) {
+            (SocketType::Datagram, None) => {
+                let c_socket_name = CString::new(self.socket_name.as_str())?;
+                let c_local_socket = match &self.local_socket {
+                    Some(local_socket) => Some(CString::new(local_socket.as_str())?),
+                    None => None,
+                };
+                let socket_target = unsafe {
+                    stumpless_open_socket_target(
+                        c_socket_name.as_ptr(),
+                        c_local_socket
+                            .as_ref()
+                            .map_or(std::ptr::null(), |s| s.as_ptr()),
+                    )
+                };
+
+                if socket_target.is_null() {
+                    return Err(Box::new(StumplessError));
+                }
+
+                Connection::Native(TargetPointer(socket_target))
+            }
+            // stumpless can't attach credentials, so this needs a socket of its own
+            (SocketType::Datagram, Some(_)) => {
+                let socket = match &self.local_socket {
+                    Some(local_socket) => UnixDatagram::bind(local_socket)?,
+                    None => UnixDatagram::unbound()?,
+                };
+                socket.connect(&self.socket_name)?;
+
+                Connection::Datagram(socket)
+            }
+            (SocketType::Stream, _) => {
+                Connection::Stream(Mutex::new(UnixStream::connect(&self.socket_name)?))
+            }
+        };
+
+        Ok(SocketTarget {
+            socket_name: self.socket_name,
+            credentials: self.credentials,
+            connection,
+        })
+    }
+}
+
+// only connects a datagram socket, which the daemon doesn't see, so that
+// probing a stream socket doesn't leave it with a connection to accept
+fn probe(socket_name: &str) -> Option<SocketType> {
+    let metadata = std::fs::metadata(socket_name).ok()?;
+    if !metadata.file_type().is_socket() {
+        return None;
+    }
 
-        if socket_target.is_null() {
-            Err(Box::new(StumplessError))
-        } else {
-            Ok(SocketTarget {
-                target: socket_target,
-            })
+    let datagram = UnixDatagram::unbound().ok()?;
+    match datagram.connect(socket_name) {
+        Ok(()) => Some(SocketType::Datagram),
+        Err(error) if error.raw_os_error() == Some(libc::EPROTOTYPE) => Some(SocketType::Stream),
+        // nothing is listening on a socket left behind by a daemon
+        Err(_) => None,
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn send_with_credentials(
+    socket: &impl AsRawFd,
+    message: &[u8],
+    credentials: &Credentials,
+) -> io::Result<usize> {
+    let mut ucred = libc::ucred {
+        pid: credentials.pid as libc::pid_t,
+        uid: credentials.uid,
+        gid: credentials.gid,
+    };
+
+    match send_with_control(socket, message, libc::SCM_CREDENTIALS, ucred) {
+        // the kernel refuses a pid that no process has, which a privileged
+        // caller can still give, so the entry goes out with the real pid and
+        // only the entry itself carries the given one
+        Err(error) if error.raw_os_error() == Some(libc::ESRCH) => {
+            ucred.pid = std::process::id() as libc::pid_t;
+            send_with_control(socket, message, libc::SCM_CREDENTIALS, ucred)
         }
+        result => result,
     }
 }
 
+#[cfg(not(target_os = "linux"))]
+fn send_with_credentials(
+    _socket: &impl AsRawFd,
+    _message: &[u8],
+    _credentials: &Credentials,
+) -> io::Result<usize> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "passing credentials is only supported on Linux",
+    ))
+}
+
 impl Target for SocketTarget {
-    fn get_pointer(&self) -> *mut stumpless_target {
-        self.target
+    fn log(&self, entry: &Entry) -> Result<usize, Box<dyn Error>> {
+        match &self.connection {
+            Connection::Native(target) => add_entry_to_pointer(target.0, entry),
+            Connection::Datagram(socket) => Ok(self.send_datagram(socket, &rfc5424(entry)?)?),
+            Connection::Stream(stream) => Ok(self.send_stream(stream, &rfc5424(entry)?)?),
+        }
+    }
+
+    fn log_message(&self, message: &str) -> Result<usize, Box<dyn Error>> {
+        match &self.connection {
+            Connection::Native(target) => add_message_to_pointer(target.0, message),
+            _ => self.log(&message_entry(message)?),
+        }
     }
 }
 
 impl Drop for SocketTarget {
     fn drop(&mut self) {
-        unsafe {
-            stumpless_close_socket_target(self.target);
+        if let Connection::Native(target) = self.connection {
+            unsafe {
+                stumpless_close_socket_target(target.0);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read};
+    use std::os::unix::net::UnixListener;
+    use std::path::PathBuf;
+    use std::thread;
+
+    use crate::{Facility, Severity};
+
+    fn temp_socket(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("stumpless-{}-{}.sock", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn entry(message: &str) -> Entry {
+        Entry::new(Facility::User, Severity::Notice, "app", "msgid", message).unwrap()
+    }
+
+    #[test]
+    fn stream_sockets_get_one_line_per_entry() {
+        let path = temp_socket("stream");
+        let listener = UnixListener::bind(&path).unwrap();
+        let target = SocketTarget::builder(&path.to_string_lossy())
+            .socket_type(SocketType::Stream)
+            .build()
+            .unwrap();
+        assert_eq!(target.socket_type(), SocketType::Stream);
+
+        target.log(&entry("first")).unwrap();
+        target.log_message("second").unwrap();
+        drop(target);
+
+        let (stream, _) = listener.accept().unwrap();
+        let lines: Vec<String> = BufReader::new(stream)
+            .lines()
+            .map(|line| line.unwrap())
+            .collect();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("<13>1 "));
+        assert!(lines[0].ends_with(&format!(" app {} msgid - first", std::process::id())));
+        assert!(lines[1].ends_with(" second"));
+    }
+
+    #[test]
+    fn stream_sockets_get_the_whole_of_a_large_entry() {
+        let path = temp_socket("stream-large");
+        let listener = UnixListener::bind(&path).unwrap();
+        let target = SocketTarget::builder(&path.to_string_lossy())
+            .socket_type(SocketType::Stream)
+            .credentials(Credentials::current())
+            .build()
+            .unwrap();
+
+        // more than the socket buffer holds, so it can't go out in one write
+        let message = "x".repeat(4 * 1024 * 1024);
+        let reader = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut received = String::new();
+            stream.read_to_string(&mut received).unwrap();
+            received
+        });
+
+        let sent = target.log_message(&message).unwrap();
+        drop(target);
+        let received = reader.join().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(sent, received.len());
+        assert!(received.ends_with(&format!(" {}\n", message)));
+    }
+
+    #[test]
+    fn probes_the_socket_type() {
+        let datagram_path = temp_socket("probe-datagram");
+        let stream_path = temp_socket("probe-stream");
+        let stale_path = temp_socket("probe-stale");
+        let _datagram = UnixDatagram::bind(&datagram_path).unwrap();
+        let listener = UnixListener::bind(&stream_path).unwrap();
+        drop(UnixDatagram::bind(&stale_path).unwrap());
+
+        let datagram = probe(&datagram_path.to_string_lossy());
+        let stream = probe(&stream_path.to_string_lossy());
+        let stale = probe(&stale_path.to_string_lossy());
+        let regular = probe(file!());
+        let missing = probe("/nonexistent/stumpless.sock");
+
+        listener.set_nonblocking(true).unwrap();
+        let accepted = listener.accept();
+        for path in [&datagram_path, &stream_path, &stale_path] {
+            std::fs::remove_file(path).unwrap();
+        }
+
+        assert_eq!(datagram, Some(SocketType::Datagram));
+        assert_eq!(stream, Some(SocketType::Stream));
+        assert_eq!(stale, None);
+        assert_eq!(regular, None);
+        assert_eq!(missing, None);
+        assert_eq!(
+            accepted.unwrap_err().kind(),
+            io::ErrorKind::WouldBlock,
+            "probing should not connect to a stream socket"
+        );
+    }
+
+    #[test]
+    fn discovers_the_first_socket_in_order() {
+        let datagram_path = temp_socket("discover-datagram");
+        let stream_path = temp_socket("discover-stream");
+        let _datagram = UnixDatagram::bind(&datagram_path).unwrap();
+        let _listener = UnixListener::bind(&stream_path).unwrap();
+        let datagram_name = datagram_path.to_string_lossy();
+        let stream_name = stream_path.to_string_lossy();
+
+        let stream_first = SocketTarget::discover_from(&[
+            "/nonexistent/stumpless.sock",
+            &stream_name,
+            &datagram_name,
+        ])
+        .unwrap();
+        let datagram_first =
+            SocketTarget::discover_from(&[file!(), &datagram_name, &stream_name]).unwrap();
+        let none = SocketTarget::discover_from(&["/nonexistent/stumpless.sock", file!()]);
+        std::fs::remove_file(&datagram_path).unwrap();
+        std::fs::remove_file(&stream_path).unwrap();
+
+        assert_eq!(stream_first.socket_name(), stream_name);
+        assert_eq!(stream_first.socket_type(), SocketType::Stream);
+        assert_eq!(datagram_first.socket_name(), datagram_name);
+        assert_eq!(datagram_first.socket_type(), SocketType::Datagram);
+        assert!(none
+            .err()
+            .unwrap()
+            .to_string()
+            .starts_with("no syslog socket found, tried /nonexistent/stumpless.sock, "));
+    }
+
+    // receives a datagram along with the credentials the kernel attached
+    #[cfg(target_os = "linux")]
+    fn receive_with_credentials(socket: &UnixDatagram) -> (String, libc::ucred) {
+        let mut buffer = [0u8; 1024];
+        let mut control = [0u64; 8];
+        let mut iov = libc::iovec {
+            iov_base: buffer.as_mut_ptr().cast(),
+            iov_len: buffer.len(),
+        };
+        let mut header: libc::msghdr = unsafe { std::mem::zeroed() };
+        header.msg_iov = &mut iov;
+        header.msg_iovlen = 1;
+        header.msg_control = control.as_mut_ptr().cast();
+        header.msg_controllen = std::mem::size_of_val(&control) as _;
+
+        let (received, credentials) = unsafe {
+            let received = libc::recvmsg(socket.as_raw_fd(), &mut header, 0);
+            assert!(received >= 0);
+            let control_header = libc::CMSG_FIRSTHDR(&header);
+            assert!(!control_header.is_null());
+            assert_eq!((*control_header).cmsg_type, libc::SCM_CREDENTIALS);
+            let credentials =
+                std::ptr::read_unaligned(libc::CMSG_DATA(control_header) as *const libc::ucred);
+            (received as usize, credentials)
+        };
+
+        (
+            String::from_utf8_lossy(&buffer[..received]).into_owned(),
+            credentials,
+        )
+    }
+
+    // a receiver with SO_PASSCRED set, like journald's syslog socket
+    #[cfg(target_os = "linux")]
+    fn credentials_receiver(path: &PathBuf) -> UnixDatagram {
+        let socket = UnixDatagram::bind(path).unwrap();
+        let enabled: libc::c_int = 1;
+        let result = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_PASSCRED,
+                (&enabled as *const libc::c_int).cast(),
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        assert_eq!(result, 0);
+        socket
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn passes_the_credentials() {
+        let path = temp_socket("credentials");
+        let receiver = credentials_receiver(&path);
+        let target = SocketTarget::builder(&path.to_string_lossy())
+            .credentials(Credentials::current())
+            .build()
+            .unwrap();
+
+        target.log_message("with credentials").unwrap();
+        let (message, credentials) = receive_with_credentials(&receiver);
+        std::fs::remove_file(&path).unwrap();
+
+        let current = Credentials::current();
+        assert!(message.ends_with(" with credentials"));
+        assert_eq!(credentials.pid as u32, current.pid);
+        assert_eq!(credentials.uid, current.uid);
+        assert_eq!(credentials.gid, current.gid);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn falls_back_to_the_real_pid_for_a_missing_process() {
+        let missing = Credentials {
+            pid: 0x3fff_ffff,
+            ..Credentials::current()
+        };
+        // without CAP_SYS_ADMIN the kernel refuses any other pid outright
+        if !missing.permitted() {
+            return;
         }
+
+        let path = temp_socket("credentials-missing");
+        let receiver = credentials_receiver(&path);
+        let target = SocketTarget::builder(&path.to_string_lossy())
+            .credentials(missing)
+            .build()
+            .unwrap();
+
+        target.log(&entry("from a missing process")).unwrap();
+        let (message, credentials) = receive_with_credentials(&receiver);
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(message.ends_with(" from a missing process"));
+        assert_eq!(credentials.pid as u32, std::process::id());
+    }
+
+    #[test]
+    fn binds_the_local_socket() {
+        let path = temp_socket("remote");
+        let local_path = temp_socket("local");
+        let server = UnixDatagram::bind(&path).unwrap();
+        let target = SocketTarget::builder(&path.to_string_lossy())
+            .local_socket(&local_path.to_string_lossy())
+            .credentials(Credentials::current())
+            .build()
+            .unwrap();
+
+        target.log_message("from the local socket").unwrap();
+        let mut buffer = [0u8; 1024];
+        let (length, sender) = server.recv_from(&mut buffer).unwrap();
+        drop(target);
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&local_path).unwrap();
+
+        assert_eq!(sender.as_pathname(), Some(local_path.as_path()));
+        assert!(String::from_utf8_lossy(&buffer[..length]).ends_with(" from the local socket"));
+    }
+
+    #[test]
+    fn own_credentials_are_permitted() {
+        assert!(Credentials::current().permitted());
+    }
+
+    #[test]
+    fn other_pids_need_cap_sys_admin() {
+        let credentials = Credentials {
+            pid: std::process::id() + 1,
+            ..Credentials::current()
+        };
+
+        assert_eq!(
+            credentials.permitted(),
+            effective_capabilities() & (1 << CAP_SYS_ADMIN) != 0
+        );
+    }
+
+    #[test]
+    fn other_users_need_cap_setuid() {
+        let credentials = Credentials {
+            uid: Credentials::current().uid + 1,
+            ..Credentials::current()
+        };
+
+        assert_eq!(
+            credentials.permitted(),
+            effective_capabilities() & (1 << CAP_SETUID) != 0
+        );
     }
 }
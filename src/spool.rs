@@ -0,0 +1,682 @@
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::timestamp::Timestamp;
+use crate::{Entry, Facility, Severity, StumplessError, Target};
+
+const ACK_FILENAME: &str = "ack";
+
+// how many replayed entries may go by before the ack is written, so that a
+// crash sends at most this many again
+const ACK_INTERVAL: usize = 64;
+
+// holds entries the wrapped target could not take in segment files on disk,
+// and sends them on in order once it accepts entries again
+//
+// stumpless does not expose the timestamp of an entry, so one rebuilt from the
+// spool is stamped when it is replayed, and carries the time it was spooled in
+// a time param of a spool element
+pub struct SpoolTarget<T: Target> {
+    inner: T,
+    directory: PathBuf,
+    max_segment_size: u64,
+    max_size: u64,
+    initial_replay_backoff: Duration,
+    max_replay_backoff: Duration,
+    state: Mutex<SpoolState>,
+}
+
+pub struct SpoolTargetBuilder<T: Target> {
+    inner: T,
+    directory: PathBuf,
+    max_segment_size: u64,
+    max_size: u64,
+    initial_replay_backoff: Duration,
+    max_replay_backoff: Duration,
+}
+
+struct SpoolState {
+    // segment numbers and lengths, oldest first
+    segments: VecDeque<(u64, u64)>,
+    writer: Option<File>,
+    next_segment: u64,
+    // the segment and offset of the next entry to replay
+    ack: (u64, u64),
+    dropped: u64,
+    // while the wrapped target is failing, entries go straight to the spool
+    // until this passes, rather than each one waiting on another failure
+    next_replay: Option<Instant>,
+    replay_backoff: Duration,
+}
+
+impl<T: Target> SpoolTarget<T> {
+    pub fn new(inner: T, directory: &str) -> Result<Self, Box<dyn Error>> {
+        SpoolTarget::builder(inner, directory).build()
+    }
+
+    pub fn builder(inner: T, directory: &str) -> SpoolTargetBuilder<T> {
+        SpoolTargetBuilder {
+            inner,
+            directory: PathBuf::from(directory),
+            max_segment_size: 1024 * 1024,
+            max_size: 64 * 1024 * 1024,
+            initial_replay_backoff: Duration::from_secs(1),
+            max_replay_backoff: Duration::from_secs(60),
+        }
+    }
+
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.state.lock().unwrap().is_empty()
+    }
+
+    pub fn pending_bytes(&self) -> u64 {
+        let state = self.state.lock().unwrap();
+        let total: u64 = state.segments.iter().map(|(_, length)| length).sum();
+
+        match state.segments.front() {
+            Some(&(segment, _)) if segment == state.ack.0 => total - state.ack.1,
+            _ => total,
+        }
+    }
+
+    // entries thrown away because the spool reached its size cap
+    pub fn dropped(&self) -> u64 {
+        self.state.lock().unwrap().dropped
+    }
+
+    // sends as many spooled entries on as the wrapped target takes, returning
+    // how many were sent, whether or not the replay backoff has passed
+    pub fn replay(&self) -> Result<usize, Box<dyn Error>> {
+        let mut state = self.state.lock().unwrap();
+        let replayed = self.replay_locked(&mut state);
+
+        if state.is_empty() {
+            self.reset_backoff(&mut state);
+        } else {
+            self.back_off(&mut state);
+        }
+
+        replayed
+    }
+
+    fn back_off(&self, state: &mut SpoolState) {
+        state.next_replay = Some(Instant::now() + state.replay_backoff);
+        state.replay_backoff = (state.replay_backoff * 2).min(self.max_replay_backoff);
+    }
+
+    fn reset_backoff(&self, state: &mut SpoolState) {
+        state.next_replay = None;
+        state.replay_backoff = self.initial_replay_backoff;
+    }
+
+    fn replay_locked(&self, state: &mut SpoolState) -> Result<usize, Box<dyn Error>> {
+        let mut replayed = 0;
+        let mut unacked = 0;
+
+        while let Some(&(segment, length)) = state.segments.front() {
+            if state.ack.0 != segment {
+                state.ack = (segment, 0);
+            }
+
+            let contents = fs::read(self.segment_path(segment))?;
+            let end = (length as usize).min(contents.len());
+            let mut offset = state.ack.1 as usize;
+
+            while offset < end {
+                let line_end = match contents[offset..end].iter().position(|&b| b == b'\n') {
+                    Some(position) => offset + position,
+                    None => end,
+                };
+
+                match deserialize(&String::from_utf8_lossy(&contents[offset..line_end])) {
+                    Ok(entry) => {
+                        if self.inner.log(&entry).is_err() {
+                            if unacked > 0 {
+                                self.write_ack(state.ack)?;
+                            }
+                            return Ok(replayed);
+                        }
+                        replayed += 1;
+                    }
+                    // a damaged line can never be sent, so it is skipped
+                    Err(_) => state.dropped += 1,
+                }
+
+                offset = line_end + 1;
+                state.ack = (segment, offset as u64);
+                unacked += 1;
+                if unacked == ACK_INTERVAL {
+                    self.write_ack(state.ack)?;
+                    unacked = 0;
+                }
+            }
+
+            // every entry in the segment has been sent, and a restart skips
+            // to the next segment once this one is gone
+            unacked = 0;
+            state.segments.pop_front();
+            state.writer = None;
+            fs::remove_file(self.segment_path(segment))?;
+        }
+
+        Ok(replayed)
+    }
+
+    fn spool(&self, state: &mut SpoolState, entry: &Entry) -> Result<usize, Box<dyn Error>> {
+        let mut line = serialize(entry)?;
+        // an entry that was already spooled once keeps its first time
+        if !entry
+            .get_elements()?
+            .iter()
+            .any(|element| element.name == "spool")
+        {
+            line.push_str(&format!("\t[spool\ttime={}", Timestamp::now().to_rfc3339()));
+        }
+        line.push('\n');
+        let line_length = line.len() as u64;
+
+        let full = match state.segments.back() {
+            Some(&(_, length)) => length > 0 && length + line_length > self.max_segment_size,
+            None => true,
+        };
+
+        if full {
+            let segment = state.next_segment;
+            state.next_segment += 1;
+            state.writer = Some(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(self.segment_path(segment))?,
+            );
+            state.segments.push_back((segment, 0));
+        } else if state.writer.is_none() {
+            let &(segment, _) = state.segments.back().unwrap();
+            state.writer = Some(
+                OpenOptions::new()
+                    .append(true)
+                    .open(self.segment_path(segment))?,
+            );
+        }
+
+        state.writer.as_mut().unwrap().write_all(line.as_bytes())?;
+        state.segments.back_mut().unwrap().1 += line_length;
+
+        self.enforce_max_size(state)?;
+
+        Ok(line.len())
+    }
+
+    // the oldest entries go first once the cap is reached, as the newest are
+    // the most likely to still be of interest
+    fn enforce_max_size(&self, state: &mut SpoolState) -> Result<(), Box<dyn Error>> {
+        while state.segments.len() > 1
+            && state.segments.iter().map(|(_, length)| length).sum::<u64>() > self.max_size
+        {
+            let (segment, _) = state.segments.pop_front().unwrap();
+            let path = self.segment_path(segment);
+            let offset = if state.ack.0 == segment {
+                state.ack.1 as usize
+            } else {
+                0
+            };
+
+            let contents = fs::read(&path)?;
+            state.dropped += contents
+                .get(offset..)
+                .map_or(0, |rest| rest.iter().filter(|&&b| b == b'\n').count())
+                as u64;
+            fs::remove_file(path)?;
+
+            state.ack = (state.segments.front().unwrap().0, 0);
+            self.write_ack(state.ack)?;
+        }
+
+        Ok(())
+    }
+
+    fn segment_path(&self, segment: u64) -> PathBuf {
+        segment_path(&self.directory, segment)
+    }
+
+    // written to the side and renamed into place, so that a crash leaves
+    // either the old or the new offset behind, and synced along with the
+    // directory so that the rename itself survives a power loss
+    fn write_ack(&self, ack: (u64, u64)) -> io::Result<()> {
+        let ack_path = self.directory.join(ACK_FILENAME);
+        let temporary_path = self.directory.join(format!("{}.tmp", ACK_FILENAME));
+
+        let mut temporary = File::create(&temporary_path)?;
+        temporary.write_all(format!("{} {}\n", ack.0, ack.1).as_bytes())?;
+        temporary.sync_all()?;
+        fs::rename(temporary_path, ack_path)?;
+        File::open(&self.directory)?.sync_all()
+    }
+}
+
+impl<T: Target> SpoolTargetBuilder<T> {
+    // a new segment is started once the current one would grow past this
+    pub fn max_segment_size(mut self, max_segment_size: u64) -> Self {
+        self.max_segment_size = max_segment_size;
+        self
+    }
+
+    pub fn max_size(mut self, max_size: u64) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    // how long to wait before trying the wrapped target again after it fails,
+    // doubling on each further failure up to the maximum
+    pub fn replay_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_replay_backoff = initial;
+        self.max_replay_backoff = max;
+        self
+    }
+
+    pub fn build(self) -> Result<SpoolTarget<T>, Box<dyn Error>> {
+        fs::create_dir_all(&self.directory)?;
+        let state = recover(&self.directory, self.initial_replay_backoff)?;
+
+        Ok(SpoolTarget {
+            inner: self.inner,
+            directory: self.directory,
+            max_segment_size: self.max_segment_size,
+            max_size: self.max_size,
+            initial_replay_backoff: self.initial_replay_backoff,
+            max_replay_backoff: self.max_replay_backoff,
+            state: Mutex::new(state),
+        })
+    }
+}
+
+impl<T: Target> Target for SpoolTarget<T> {
+    fn log(&self, entry: &Entry) -> Result<usize, Box<dyn Error>> {
+        let mut state = self.state.lock().unwrap();
+
+        // spooled entries go first so that order is kept
+        if !state.is_empty() && state.replay_due() {
+            let replayed = self.replay_locked(&mut state);
+            if state.is_empty() {
+                self.reset_backoff(&mut state);
+            } else {
+                self.back_off(&mut state);
+            }
+            replayed?;
+        }
+
+        if state.is_empty() {
+            match self.inner.log(entry) {
+                Ok(length) => return Ok(length),
+                Err(_) => self.back_off(&mut state),
+            }
+        }
+
+        self.spool(&mut state, entry)
+    }
+}
+
+impl SpoolState {
+    fn is_empty(&self) -> bool {
+        match self.segments.len() {
+            0 => true,
+            1 => self.segments[0].0 == self.ack.0 && self.segments[0].1 <= self.ack.1,
+            _ => false,
+        }
+    }
+
+    fn replay_due(&self) -> bool {
+        self.next_replay
+            .map_or(true, |next_replay| Instant::now() >= next_replay)
+    }
+}
+
+fn recover(directory: &Path, replay_backoff: Duration) -> Result<SpoolState, Box<dyn Error>> {
+    let mut segments = Vec::new();
+    for dir_entry in fs::read_dir(directory)? {
+        let dir_entry = dir_entry?;
+        let filename = dir_entry.file_name().to_string_lossy().into_owned();
+
+        if let Some(segment) = filename
+            .strip_prefix("spool-")
+            .and_then(|rest| rest.strip_suffix(".log"))
+            .and_then(|number| number.parse::<u64>().ok())
+        {
+            segments.push((segment, dir_entry.metadata()?.len()));
+        }
+    }
+    segments.sort_unstable();
+
+    let ack = match fs::read_to_string(directory.join(ACK_FILENAME)) {
+        Ok(contents) => parse_ack(&contents)?,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => (0, 0),
+        Err(error) => return Err(Box::new(error)),
+    };
+
+    // segments before the acknowledged one were already sent in full
+    let mut segments: VecDeque<(u64, u64)> = segments.into_iter().collect();
+    while segments
+        .front()
+        .map_or(false, |&(segment, _)| segment < ack.0)
+    {
+        let (segment, _) = segments.pop_front().unwrap();
+        fs::remove_file(segment_path(directory, segment))?;
+    }
+
+    // a crash while appending can leave a partial entry at the end
+    if let Some((segment, length)) = segments.back_mut() {
+        let path = segment_path(directory, *segment);
+        let contents = fs::read(&path)?;
+        let complete = contents
+            .iter()
+            .rposition(|&b| b == b'\n')
+            .map_or(0, |i| i + 1) as u64;
+
+        if complete < *length {
+            OpenOptions::new()
+                .write(true)
+                .open(&path)?
+                .set_len(complete)?;
+            *length = complete;
+        }
+    }
+
+    let ack = match segments.front() {
+        Some(&(segment, length)) if segment == ack.0 => (segment, ack.1.min(length)),
+        Some(&(segment, _)) => (segment, 0),
+        None => ack,
+    };
+    let next_segment = segments.back().map_or(ack.0, |&(segment, _)| segment) + 1;
+
+    Ok(SpoolState {
+        segments,
+        writer: None,
+        next_segment,
+        ack,
+        dropped: 0,
+        next_replay: None,
+        replay_backoff,
+    })
+}
+
+fn segment_path(directory: &Path, segment: u64) -> PathBuf {
+    directory.join(format!("spool-{:020}.log", segment))
+}
+
+fn parse_ack(contents: &str) -> Result<(u64, u64), Box<dyn Error>> {
+    match contents.trim().split_once(' ') {
+        Some((segment, offset)) => Ok((segment.parse()?, offset.parse()?)),
+        None => Err(Box::new(StumplessError)),
+    }
+}
+
+// one entry per line, with tab separated fields: the prival, app name, msgid,
+// procid (empty if unset) and message, then a field starting with [ for each
+// element followed by a name=value field for each of its params
+fn serialize(entry: &Entry) -> Result<String, Box<dyn Error>> {
+    let mut fields = vec![
+        entry.get_prival()?.to_string(),
+        escape(&entry.get_app_name()?),
+        escape(&entry.get_msgid()?),
+        escape(&entry.get_procid()?.unwrap_or_default()),
+        escape(&entry.get_message()?),
+    ];
+
+    for element in entry.get_elements()? {
+        fields.push(format!("[{}", escape(&element.name)));
+        for (name, value) in element.params {
+            fields.push(format!("{}={}", escape(&name), escape(&value)));
+        }
+    }
+
+    Ok(fields.join("\t"))
+}
+
+fn deserialize(line: &str) -> Result<Entry, Box<dyn Error>> {
+    let mut fields = line.split('\t');
+    let mut next_field = || fields.next().ok_or(StumplessError);
+
+    let prival = next_field()?.parse::<i32>()?;
+    let app_name = unescape(next_field()?);
+    let msgid = unescape(next_field()?);
+    let procid = unescape(next_field()?);
+    let message = unescape(next_field()?);
+
+    let entry = Entry::new(Facility::User, Severity::Info, &app_name, &msgid, &message)?;
+    entry.set_prival(prival)?;
+    if !procid.is_empty() {
+        entry.set_procid(&procid)?;
+    }
+
+    let mut element_name = None;
+    for field in fields {
+        if let Some(name) = field.strip_prefix('[') {
+            let name = unescape(name);
+            entry.add_element(&name)?;
+            element_name = Some(name);
+        } else {
+            let element_name = element_name.as_ref().ok_or(StumplessError)?;
+            let (name, value) = field.split_once('=').ok_or(StumplessError)?;
+            entry.add_param(element_name, &unescape(name), &unescape(value))?;
+        }
+    }
+
+    Ok(entry)
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+}
+
+fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('t') => unescaped.push('\t'),
+            Some('n') => unescaped.push('\n'),
+            Some(other) => unescaped.push(other),
+            None => unescaped.push('\\'),
+        }
+    }
+
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Element;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[derive(Clone, Default)]
+    struct Flaky {
+        up: Arc<AtomicBool>,
+        attempts: Arc<AtomicUsize>,
+        messages: Arc<Mutex<Vec<String>>>,
+        elements: Arc<Mutex<Vec<Vec<Element>>>>,
+        // fails once this many entries have been taken
+        limit: Arc<Mutex<Option<usize>>>,
+    }
+
+    impl Target for Flaky {
+        fn log(&self, entry: &Entry) -> Result<usize, Box<dyn Error>> {
+            self.attempts.fetch_add(1, Ordering::SeqCst);
+            if !self.up.load(Ordering::SeqCst)
+                || *self.limit.lock().unwrap() == Some(self.messages.lock().unwrap().len())
+            {
+                return Err(Box::new(StumplessError));
+            }
+
+            let message = entry.get_message()?;
+            let length = message.len();
+            self.messages.lock().unwrap().push(message);
+            self.elements.lock().unwrap().push(entry.get_elements()?);
+            Ok(length)
+        }
+    }
+
+    struct Directory(PathBuf);
+
+    impl Directory {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "stumpless-spool-{}-{}",
+                name,
+                std::process::id()
+            ));
+            let _ = fs::remove_dir_all(&path);
+            Directory(path)
+        }
+
+        fn spool(&self, inner: &Flaky, backoff: Duration) -> SpoolTarget<Flaky> {
+            SpoolTarget::builder(inner.clone(), &self.0.to_string_lossy())
+                .replay_backoff(backoff, backoff * 4)
+                .build()
+                .unwrap()
+        }
+    }
+
+    impl Drop for Directory {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn log(spool: &SpoolTarget<Flaky>, message: &str) {
+        let entry = Entry::new(Facility::User, Severity::Info, "app", "msgid", message).unwrap();
+        spool.log(&entry).unwrap();
+    }
+
+    #[test]
+    fn waits_for_the_backoff_before_replaying() {
+        let directory = Directory::new("backoff");
+        let inner = Flaky::default();
+        let spool = directory.spool(&inner, Duration::from_secs(60));
+
+        for i in 0..10 {
+            log(&spool, &format!("entry {}", i));
+        }
+
+        // only the first entry tried the target, the rest went to the spool
+        assert_eq!(inner.attempts.load(Ordering::SeqCst), 1);
+        assert!(!spool.is_empty());
+
+        inner.up.store(true, Ordering::SeqCst);
+        log(&spool, "entry 10");
+        assert!(inner.messages.lock().unwrap().is_empty());
+
+        assert_eq!(spool.replay().unwrap(), 11);
+        assert!(spool.is_empty());
+    }
+
+    #[test]
+    fn replays_in_order_once_the_backoff_passes() {
+        let directory = Directory::new("order");
+        let inner = Flaky::default();
+        let spool = directory.spool(&inner, Duration::from_millis(50));
+
+        log(&spool, "first");
+        log(&spool, "second");
+        inner.up.store(true, Ordering::SeqCst);
+        std::thread::sleep(Duration::from_millis(100));
+        log(&spool, "third");
+
+        assert!(spool.is_empty());
+        assert_eq!(
+            *inner.messages.lock().unwrap(),
+            ["first", "second", "third"]
+        );
+    }
+
+    #[test]
+    fn replayed_entries_carry_the_time_they_were_spooled() {
+        let directory = Directory::new("time");
+        let inner = Flaky::default();
+        let spool = directory.spool(&inner, Duration::from_secs(60));
+
+        let before = Timestamp::now().to_rfc3339();
+        log(&spool, "spooled");
+        let after = Timestamp::now().to_rfc3339();
+
+        inner.up.store(true, Ordering::SeqCst);
+        spool.replay().unwrap();
+        log(&spool, "live");
+
+        let elements = inner.elements.lock().unwrap();
+        assert_eq!(elements[0].len(), 1);
+        assert_eq!(elements[0][0].name, "spool");
+        assert_eq!(elements[0][0].params[0].0, "time");
+        let time = &elements[0][0].params[0].1;
+        assert!(before <= *time && *time <= after, "{}", time);
+
+        assert!(elements[1].is_empty());
+    }
+
+    #[test]
+    fn acknowledges_replayed_entries_when_the_target_fails() {
+        let directory = Directory::new("ack");
+        let inner = Flaky::default();
+        let spool = directory.spool(&inner, Duration::from_secs(60));
+
+        for i in 0..ACK_INTERVAL + 3 {
+            log(&spool, &format!("entry {}", i));
+        }
+        inner.up.store(true, Ordering::SeqCst);
+        *inner.limit.lock().unwrap() = Some(ACK_INTERVAL + 1);
+        assert_eq!(spool.replay().unwrap(), ACK_INTERVAL + 1);
+
+        // a restart picks up from the failed entry rather than the last batch
+        drop(spool);
+        *inner.limit.lock().unwrap() = None;
+        let spool = directory.spool(&inner, Duration::from_secs(60));
+        assert_eq!(spool.replay().unwrap(), 2);
+        assert!(spool.is_empty());
+
+        let messages = inner.messages.lock().unwrap();
+        assert_eq!(messages.len(), ACK_INTERVAL + 3);
+        assert_eq!(
+            messages[ACK_INTERVAL + 1],
+            format!("entry {}", ACK_INTERVAL + 1)
+        );
+    }
+
+    #[test]
+    fn recovers_spooled_entries_after_a_restart() {
+        let directory = Directory::new("restart");
+        let inner = Flaky::default();
+
+        let spool = directory.spool(&inner, Duration::from_secs(60));
+        log(&spool, "spooled");
+        drop(spool);
+
+        inner.up.store(true, Ordering::SeqCst);
+        let spool = directory.spool(&inner, Duration::from_secs(60));
+        log(&spool, "live");
+
+        assert_eq!(*inner.messages.lock().unwrap(), ["spooled", "live"]);
+    }
+}
@@ -1,16 +1,101 @@
+use crate::entry::Entry;
 use crate::error::StumplessError;
+use crate::facility::Facility;
+use crate::severity::Severity;
+use std::cell::RefCell;
 use std::error::Error;
 use std::ffi::CString;
-use stumpless_sys::{stumpless_add_message_str, stumpless_target};
+use std::rc::Rc;
+use stumpless_sys::{
+    stump_str, stumpless_add_entry, stumpless_add_log_str, stumpless_add_message_str,
+    stumpless_get_current_target, stumpless_set_current_target, stumpless_target,
+};
+
+pub(crate) mod sealed {
+    pub trait Sealed {}
+}
 
 pub trait Target {
+    fn log(&self, entry: &Entry) -> Result<usize, Box<dyn Error>>;
+
+    fn log_message(&self, message: &str) -> Result<usize, Box<dyn Error>> {
+        self.log(&message_entry(message)?)
+    }
+
+    // targets opened in stumpless hand out their pointer so that they can
+    // also be made the library's current target
+    fn native_pointer(&self) -> Option<*mut stumpless_target> {
+        None
+    }
+}
+
+pub(crate) fn message_entry(message: &str) -> Result<Entry, Box<dyn Error>> {
+    Entry::new(Facility::User, Severity::Info, "-", "-", message)
+}
+
+// a target opened in stumpless, which locks each target while it is in use
+// so that the pointer can be shared across threads
+#[derive(Clone, Copy)]
+pub(crate) struct TargetPointer(pub *mut stumpless_target);
+
+unsafe impl Send for TargetPointer {}
+unsafe impl Sync for TargetPointer {}
+
+// only targets opened by this crate can hand out a pointer, so it is always
+// valid for as long as the target itself is alive
+pub trait NativeTarget: sealed::Sealed {
     fn get_pointer(&self) -> *mut stumpless_target;
 }
 
-pub fn add_message(target: &impl Target, message: &str) -> Result<u32, Box<dyn Error>> {
+impl<T: NativeTarget> Target for T {
+    fn log(&self, entry: &Entry) -> Result<usize, Box<dyn Error>> {
+        add_entry_to_pointer(self.get_pointer(), entry)
+    }
+
+    fn log_message(&self, message: &str) -> Result<usize, Box<dyn Error>> {
+        add_message_to_pointer(self.get_pointer(), message)
+    }
+
+    fn native_pointer(&self) -> Option<*mut stumpless_target> {
+        Some(self.get_pointer())
+    }
+}
+
+// lets wrapper targets hold any mix of targets chosen at runtime
+impl Target for Box<dyn Target + Send> {
+    fn log(&self, entry: &Entry) -> Result<usize, Box<dyn Error>> {
+        (**self).log(entry)
+    }
+
+    fn log_message(&self, message: &str) -> Result<usize, Box<dyn Error>> {
+        (**self).log_message(message)
+    }
+
+    fn native_pointer(&self) -> Option<*mut stumpless_target> {
+        (**self).native_pointer()
+    }
+}
+
+pub(crate) fn add_entry_to_pointer(
+    target: *mut stumpless_target,
+    entry: &Entry,
+) -> Result<usize, Box<dyn Error>> {
+    let add_result = unsafe { stumpless_add_entry(target, entry.get_pointer()) };
+
+    if add_result >= 0 {
+        Ok(add_result.try_into().unwrap())
+    } else {
+        Err(Box::new(StumplessError))
+    }
+}
+
+pub(crate) fn add_message_to_pointer(
+    target: *mut stumpless_target,
+    message: &str,
+) -> Result<usize, Box<dyn Error>> {
     let c_message = CString::new(message)?;
 
-    let add_result = unsafe { stumpless_add_message_str(target.get_pointer(), c_message.as_ptr()) };
+    let add_result = unsafe { stumpless_add_message_str(target, c_message.as_ptr()) };
 
     if add_result >= 0 {
         Ok(add_result.try_into().unwrap())
@@ -18,3 +103,180 @@ pub fn add_message(target: &impl Target, message: &str) -> Result<u32, Box<dyn E
         Err(Box::new(StumplessError))
     }
 }
+
+// targets aren't required to be Send, so each thread keeps its own current
+// target. one opened in stumpless is also made the library's current target,
+// which every thread without a target of its own falls back to.
+thread_local! {
+    static CURRENT_TARGET: RefCell<Option<CurrentTarget>> = const { RefCell::new(None) };
+}
+
+struct CurrentTarget {
+    target: Rc<dyn Target>,
+}
+
+impl Drop for CurrentTarget {
+    fn drop(&mut self) {
+        // stumpless must stop using the target before it can be closed
+        if let Some(pointer) = self.target.native_pointer() {
+            unsafe {
+                if stumpless_get_current_target() == pointer {
+                    stumpless_set_current_target(std::ptr::null_mut());
+                }
+            }
+        }
+    }
+}
+
+// the target is cloned out so that nothing is borrowed while it logs, which
+// may itself replace the current target
+fn current_target() -> Option<Rc<dyn Target>> {
+    CURRENT_TARGET.with(|current| {
+        current
+            .borrow()
+            .as_ref()
+            .map(|current| Rc::clone(&current.target))
+    })
+}
+
+pub fn add_message(
+    target: &(impl Target + ?Sized),
+    message: &str,
+) -> Result<usize, Box<dyn Error>> {
+    target.log_message(message)
+}
+
+pub fn set_current_target(target: impl Target + 'static) {
+    let target: Rc<dyn Target> = Rc::new(target);
+
+    if let Some(pointer) = target.native_pointer() {
+        unsafe {
+            stumpless_set_current_target(pointer);
+        }
+    }
+
+    // the previous target is dropped once it is no longer borrowed
+    CURRENT_TARGET.with(|current| current.replace(Some(CurrentTarget { target })));
+}
+
+pub fn stump(message: &str) -> Result<usize, Box<dyn Error>> {
+    if let Some(target) = current_target() {
+        return target.log_message(message);
+    }
+
+    // without a current target, stumpless falls back to its default target
+    let c_message = CString::new(message)?;
+    let stump_result = unsafe { stump_str(c_message.as_ptr()) };
+
+    if stump_result >= 0 {
+        Ok(stump_result.try_into().unwrap())
+    } else {
+        Err(Box::new(StumplessError))
+    }
+}
+
+pub fn stumplog(priority: i32, message: &str) -> Result<usize, Box<dyn Error>> {
+    if let Some(target) = current_target() {
+        let entry = message_entry(message)?;
+        entry.set_prival(priority)?;
+        return target.log(&entry);
+    }
+
+    let c_message = CString::new(message)?;
+    let stumplog_result = unsafe {
+        stumpless_add_log_str(stumpless_get_current_target(), priority, c_message.as_ptr())
+    };
+
+    if stumplog_result >= 0 {
+        Ok(stumplog_result.try_into().unwrap())
+    } else {
+        Err(Box::new(StumplessError))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // targets don't need to be Send to become the current target
+    struct Recording {
+        name: &'static str,
+        logged: Rc<RefCell<Vec<String>>>,
+    }
+
+    impl Target for Recording {
+        fn log(&self, entry: &Entry) -> Result<usize, Box<dyn Error>> {
+            let line = format!(
+                "{} {} {}",
+                self.name,
+                entry.get_prival()?,
+                entry.get_message()?
+            );
+            self.logged.borrow_mut().push(line);
+            Ok(entry.get_message()?.len())
+        }
+    }
+
+    // replaces the current target while it is logging
+    struct Replacing {
+        logged: Rc<RefCell<Vec<String>>>,
+    }
+
+    impl Target for Replacing {
+        fn log(&self, entry: &Entry) -> Result<usize, Box<dyn Error>> {
+            set_current_target(Recording {
+                name: "replacement",
+                logged: Rc::clone(&self.logged),
+            });
+            stump(&entry.get_message()?)
+        }
+    }
+
+    #[test]
+    fn logs_through_the_current_target() {
+        let logged = Rc::new(RefCell::new(Vec::new()));
+        set_current_target(Recording {
+            name: "first",
+            logged: Rc::clone(&logged),
+        });
+
+        assert_eq!(stump("hello").unwrap(), 5);
+        assert_eq!(stumplog(11, "failed").unwrap(), 6);
+
+        assert_eq!(*logged.borrow(), ["first 14 hello", "first 11 failed"]);
+    }
+
+    #[test]
+    fn replaces_the_current_target() {
+        let logged = Rc::new(RefCell::new(Vec::new()));
+        set_current_target(Recording {
+            name: "first",
+            logged: Rc::clone(&logged),
+        });
+        stump("one").unwrap();
+
+        set_current_target(Recording {
+            name: "second",
+            logged: Rc::clone(&logged),
+        });
+        stump("two").unwrap();
+
+        assert_eq!(*logged.borrow(), ["first 14 one", "second 14 two"]);
+    }
+
+    #[test]
+    fn can_be_replaced_while_logging() {
+        let logged = Rc::new(RefCell::new(Vec::new()));
+        set_current_target(Replacing {
+            logged: Rc::clone(&logged),
+        });
+
+        stump("handed over").unwrap();
+        stump("after").unwrap();
+
+        assert_eq!(
+            *logged.borrow(),
+            ["replacement 14 handed over", "replacement 14 after"]
+        );
+    }
+}
@@ -0,0 +1,248 @@
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+
+use crate::{Element, Entry, Facility, Severity, Target};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CapturedEntry {
+    pub prival: i32,
+    pub facility: Facility,
+    pub severity: Severity,
+    pub app_name: String,
+    pub msgid: String,
+    pub message: String,
+    pub elements: Vec<Element>,
+}
+
+impl CapturedEntry {
+    pub fn from_entry(entry: &Entry) -> Result<Self, Box<dyn Error>> {
+        Ok(CapturedEntry {
+            prival: entry.get_prival()?,
+            facility: entry.get_facility()?,
+            severity: entry.get_severity()?,
+            app_name: entry.get_app_name()?,
+            msgid: entry.get_msgid()?,
+            message: entry.get_message()?,
+            elements: entry.get_elements()?,
+        })
+    }
+
+    pub fn get_param(&self, element: &str, param: &str) -> Option<&str> {
+        self.elements
+            .iter()
+            .filter(|e| e.name == element)
+            .flat_map(|e| e.params.iter())
+            .find(|(name, _)| name == param)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+// clones share the same capture buffer, so a test can keep a handle after
+// giving the target away, for example to set_current_target
+#[derive(Clone, Default)]
+pub struct CapturingTarget {
+    entries: Arc<Mutex<Vec<CapturedEntry>>>,
+}
+
+impl CapturingTarget {
+    pub fn new() -> Self {
+        CapturingTarget::default()
+    }
+
+    pub fn entries(&self) -> Vec<CapturedEntry> {
+        self.entries.lock().unwrap().clone()
+    }
+
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+
+    pub fn find(&self, predicate: impl Fn(&CapturedEntry) -> bool) -> Option<CapturedEntry> {
+        self.entries
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|e| predicate(e))
+            .cloned()
+    }
+
+    #[track_caller]
+    pub fn assert_logged_matching(&self, predicate: impl Fn(&CapturedEntry) -> bool) {
+        if self.find(predicate).is_none() {
+            panic!(
+                "no matching entry was logged, captured: {:#?}",
+                self.entries()
+            );
+        }
+    }
+
+    #[track_caller]
+    pub fn assert_logged(&self, severity: Severity, contains: &str) {
+        if self
+            .find(|e| e.severity == severity && e.message.contains(contains))
+            .is_none()
+        {
+            panic!(
+                "no {:?} entry containing {:?} was logged, captured: {:#?}",
+                severity,
+                contains,
+                self.entries()
+            );
+        }
+    }
+
+    #[track_caller]
+    pub fn assert_logged_msgid(&self, severity: Severity, msgid: &str) {
+        if self
+            .find(|e| e.severity == severity && e.msgid == msgid)
+            .is_none()
+        {
+            panic!(
+                "no {:?} entry with msgid {:?} was logged, captured: {:#?}",
+                severity,
+                msgid,
+                self.entries()
+            );
+        }
+    }
+
+    #[track_caller]
+    pub fn assert_logged_param(&self, element: &str, param: &str, value: &str) {
+        if self
+            .find(|e| e.get_param(element, param) == Some(value))
+            .is_none()
+        {
+            panic!(
+                "no entry with [{} {}={:?}] was logged, captured: {:#?}",
+                element,
+                param,
+                value,
+                self.entries()
+            );
+        }
+    }
+
+    #[track_caller]
+    pub fn assert_nothing_logged(&self) {
+        let entries = self.entries();
+        if !entries.is_empty() {
+            panic!("expected no entries to be logged, captured: {:#?}", entries);
+        }
+    }
+}
+
+// nothing is formatted, so the size reported for an entry is the length of
+// its message
+impl Target for CapturingTarget {
+    fn log(&self, entry: &Entry) -> Result<usize, Box<dyn Error>> {
+        let captured = CapturedEntry::from_entry(entry)?;
+        let size = captured.message.len();
+
+        self.entries.lock().unwrap().push(captured);
+        Ok(size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn logged(target: &CapturingTarget, severity: Severity, msgid: &str, message: &str) {
+        let entry = Entry::new(Facility::Daemon, severity, "app", msgid, message).unwrap();
+        entry.add_element("origin").unwrap();
+        entry.add_param("origin", "ip", "10.0.0.1").unwrap();
+        assert_eq!(target.log(&entry).unwrap(), message.len());
+    }
+
+    #[test]
+    fn captures_entries() {
+        let target = CapturingTarget::new();
+        logged(&target, Severity::Warning, "disk", "disk almost full");
+
+        let entries = target.entries();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].facility, Facility::Daemon);
+        assert_eq!(entries[0].severity, Severity::Warning);
+        assert_eq!(entries[0].app_name, "app");
+        assert_eq!(entries[0].msgid, "disk");
+        assert_eq!(entries[0].message, "disk almost full");
+        assert_eq!(entries[0].get_param("origin", "ip"), Some("10.0.0.1"));
+        assert_eq!(entries[0].get_param("origin", "port"), None);
+    }
+
+    #[test]
+    fn clones_share_the_captured_entries() {
+        let target = CapturingTarget::new();
+        let handle = target.clone();
+
+        target.log_message("through the original").unwrap();
+        assert_eq!(handle.entries()[0].message, "through the original");
+
+        handle.clear();
+        target.assert_nothing_logged();
+    }
+
+    #[test]
+    fn finds_entries() {
+        let target = CapturingTarget::new();
+        logged(&target, Severity::Info, "start", "started");
+        logged(&target, Severity::Error, "stop", "stopped");
+
+        let found = target.find(|e| e.severity == Severity::Error).unwrap();
+        assert_eq!(found.message, "stopped");
+        assert!(target.find(|e| e.msgid == "restart").is_none());
+    }
+
+    #[test]
+    fn asserts_on_logged_entries() {
+        let target = CapturingTarget::new();
+        logged(&target, Severity::Error, "stop", "service stopped");
+
+        target.assert_logged(Severity::Error, "stopped");
+        target.assert_logged_msgid(Severity::Error, "stop");
+        target.assert_logged_param("origin", "ip", "10.0.0.1");
+        target.assert_logged_matching(|e| e.app_name == "app");
+    }
+
+    #[test]
+    #[should_panic(expected = "no Info entry containing \"stopped\" was logged")]
+    fn assert_logged_checks_the_severity() {
+        let target = CapturingTarget::new();
+        logged(&target, Severity::Error, "stop", "service stopped");
+
+        target.assert_logged(Severity::Info, "stopped");
+    }
+
+    #[test]
+    #[should_panic(expected = "no Error entry with msgid \"start\" was logged")]
+    fn assert_logged_msgid_fails_without_a_match() {
+        let target = CapturingTarget::new();
+        logged(&target, Severity::Error, "stop", "service stopped");
+
+        target.assert_logged_msgid(Severity::Error, "start");
+    }
+
+    #[test]
+    #[should_panic(expected = "no entry with [origin ip=\"10.0.0.2\"] was logged")]
+    fn assert_logged_param_fails_without_a_match() {
+        let target = CapturingTarget::new();
+        logged(&target, Severity::Error, "stop", "service stopped");
+
+        target.assert_logged_param("origin", "ip", "10.0.0.2");
+    }
+
+    #[test]
+    #[should_panic(expected = "no matching entry was logged")]
+    fn assert_logged_matching_fails_without_a_match() {
+        CapturingTarget::new().assert_logged_matching(|_| true);
+    }
+
+    #[test]
+    #[should_panic(expected = "expected no entries to be logged")]
+    fn assert_nothing_logged_fails_after_logging() {
+        let target = CapturingTarget::new();
+        target.log_message("anything").unwrap();
+
+        target.assert_nothing_logged();
+    }
+}
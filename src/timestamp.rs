@@ -0,0 +1,125 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Timestamp {
+    pub year: i64,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+    pub microsecond: u32,
+}
+
+impl Timestamp {
+    pub fn now() -> Self {
+        Timestamp::from_system_time(SystemTime::now())
+    }
+
+    pub fn from_system_time(time: SystemTime) -> Self {
+        let (seconds, microsecond) = match time.duration_since(UNIX_EPOCH) {
+            Ok(duration) => (duration.as_secs() as i64, duration.subsec_micros()),
+            Err(error) => (-(error.duration().as_secs() as i64), 0),
+        };
+
+        let days = seconds.div_euclid(86_400);
+        let seconds_of_day = seconds.rem_euclid(86_400);
+        let (year, month, day) = civil_from_days(days);
+
+        Timestamp {
+            year,
+            month,
+            day,
+            hour: (seconds_of_day / 3_600) as u32,
+            minute: (seconds_of_day % 3_600 / 60) as u32,
+            second: (seconds_of_day % 60) as u32,
+            microsecond,
+        }
+    }
+
+    pub fn date(&self) -> String {
+        format!("{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+
+    pub fn date_hour(&self) -> String {
+        format!("{}-{:02}", self.date(), self.hour)
+    }
+
+    pub fn to_rfc3339(self) -> String {
+        format!(
+            "{}T{:02}:{:02}:{:02}.{:06}Z",
+            self.date(),
+            self.hour,
+            self.minute,
+            self.second,
+            self.microsecond
+        )
+    }
+}
+
+// converts days since the epoch into a proleptic Gregorian UTC date, see
+// http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn at(seconds: u64, microseconds: u64) -> Timestamp {
+        Timestamp::from_system_time(
+            UNIX_EPOCH + Duration::from_secs(seconds) + Duration::from_micros(microseconds),
+        )
+    }
+
+    #[test]
+    fn formats_rfc3339_with_microseconds() {
+        assert_eq!(
+            at(1_659_180_645, 123_456).to_rfc3339(),
+            "2022-07-30T11:30:45.123456Z"
+        );
+        assert_eq!(at(0, 7).to_rfc3339(), "1970-01-01T00:00:00.000007Z");
+    }
+
+    #[test]
+    fn formats_dates_for_rotation() {
+        let timestamp = at(1_659_180_645, 0);
+
+        assert_eq!(timestamp.date(), "2022-07-30");
+        assert_eq!(timestamp.date_hour(), "2022-07-30-11");
+        assert_eq!(at(3_600 * 5, 0).date_hour(), "1970-01-01-05");
+    }
+
+    #[test]
+    fn handles_times_before_the_epoch() {
+        let timestamp = Timestamp::from_system_time(UNIX_EPOCH - Duration::from_secs(1));
+
+        assert_eq!(timestamp.to_rfc3339(), "1969-12-31T23:59:59.000000Z");
+    }
+
+    #[test]
+    fn converts_days_to_civil_dates() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+        assert_eq!(civil_from_days(47_540), (2100, 2, 28));
+        assert_eq!(civil_from_days(47_541), (2100, 3, 1));
+        assert_eq!(civil_from_days(-719_468), (0, 3, 1));
+    }
+}
@@ -0,0 +1,533 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use rustls::{
+    Certificate, ClientConfig, ClientConnection, PrivateKey, RootCertStore, ServerName, StreamOwned,
+};
+
+use crate::format::format_entry;
+use crate::retry::RetryPolicy;
+use crate::server::{host, with_default_port};
+use crate::{Entry, Format, Target};
+
+// the port assigned to syslog over TLS by RFC 5425
+pub const DEFAULT_TLS_PORT: u16 = 6514;
+
+// the usual places distributions keep their CA bundle, used when no CA is
+// given to the builder
+pub const DEFAULT_CA_BUNDLES: [&str; 4] = [
+    "/etc/ssl/certs/ca-certificates.crt",
+    "/etc/pki/tls/certs/ca-bundle.crt",
+    "/etc/ssl/ca-bundle.pem",
+    "/etc/ssl/cert.pem",
+];
+
+type TlsStream = StreamOwned<ClientConnection, TcpStream>;
+
+pub struct TlsTarget {
+    server: String,
+    format: Format,
+    server_name: ServerName,
+    config: Arc<ClientConfig>,
+    retry: RetryPolicy,
+    // empty once the connection is lost, until the next entry reconnects
+    stream: Mutex<Option<TlsStream>>,
+}
+
+pub struct TlsTargetBuilder {
+    server: String,
+    format: Format,
+    server_name: Option<String>,
+    ca_file: Option<String>,
+    cert_file: Option<String>,
+    key_file: Option<String>,
+    sni: bool,
+    retry: RetryPolicy,
+}
+
+impl TlsTarget {
+    pub fn new(server: &str) -> Result<Self, Box<dyn Error>> {
+        TlsTarget::builder(server).build()
+    }
+
+    pub fn builder(server: &str) -> TlsTargetBuilder {
+        TlsTargetBuilder {
+            server: server.to_string(),
+            format: Format::Rfc5424,
+            server_name: None,
+            ca_file: None,
+            cert_file: None,
+            key_file: None,
+            sni: true,
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    pub fn server(&self) -> &str {
+        &self.server
+    }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        self.retry
+    }
+
+    fn send(&self, message: &str) -> io::Result<usize> {
+        let framed = format!("{} {}", message.len(), message);
+        let mut attempt = 0;
+
+        loop {
+            {
+                let mut stream = self.stream.lock().unwrap();
+
+                let error = match self.write(&mut stream, framed.as_bytes()) {
+                    Ok(()) => return Ok(framed.len()),
+                    Err(error) => error,
+                };
+
+                if attempt >= self.retry.max_retries {
+                    return Err(error);
+                }
+            }
+
+            // unlocked while waiting, so other threads can still get their
+            // entry through
+            thread::sleep(self.retry.backoff(attempt));
+            attempt += 1;
+        }
+    }
+
+    fn write(&self, stream: &mut Option<TlsStream>, framed: &[u8]) -> io::Result<()> {
+        if stream.as_mut().map_or(false, is_closed) {
+            *stream = None;
+        }
+
+        if stream.is_none() {
+            *stream = Some(connect(
+                &self.server,
+                &self.server_name,
+                &self.config,
+                &self.retry,
+            )?);
+        }
+
+        let result = write_framed(stream.as_mut().unwrap(), framed);
+        if result.is_err() {
+            *stream = None;
+        }
+
+        result
+    }
+}
+
+impl TlsTargetBuilder {
+    // the name used for SNI and checked against the server certificate,
+    // defaulting to the host the target connects to
+    pub fn server_name(mut self, server_name: &str) -> Self {
+        self.server_name = Some(server_name.to_string());
+        self
+    }
+
+    pub fn ca_file(mut self, ca_file: &str) -> Self {
+        self.ca_file = Some(ca_file.to_string());
+        self
+    }
+
+    // a client certificate and key are only needed for mutual TLS
+    pub fn client_cert(mut self, cert_file: &str, key_file: &str) -> Self {
+        self.cert_file = Some(cert_file.to_string());
+        self.key_file = Some(key_file.to_string());
+        self
+    }
+
+    pub fn sni(mut self, sni: bool) -> Self {
+        self.sni = sni;
+        self
+    }
+
+    pub fn format(mut self, format: Format) -> Self {
+        self.format = format;
+        self
+    }
+
+    // the timeouts also cover the handshake
+    pub fn retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn build(self) -> Result<TlsTarget, Box<dyn Error>> {
+        let server = with_default_port(&self.server, DEFAULT_TLS_PORT);
+        let server_name = match &self.server_name {
+            Some(server_name) => ServerName::try_from(server_name.as_str())?,
+            None => ServerName::try_from(host(&server))?,
+        };
+
+        let mut roots = RootCertStore::empty();
+        let ca_file = match &self.ca_file {
+            Some(ca_file) => ca_file.as_str(),
+            None => DEFAULT_CA_BUNDLES
+                .iter()
+                .find(|ca_file| Path::new(ca_file).exists())
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::NotFound,
+                        "no CA bundle found, give one instead",
+                    )
+                })?,
+        };
+        let (added, _) = roots.add_parsable_certificates(&read_certs(ca_file)?);
+        if added == 0 {
+            return Err(Box::new(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("no usable certificates in {}", ca_file),
+            )));
+        }
+
+        let config_builder = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots);
+        let mut config = match (&self.cert_file, &self.key_file) {
+            (Some(cert_file), Some(key_file)) => config_builder.with_client_auth_cert(
+                read_certs(cert_file)?
+                    .into_iter()
+                    .map(Certificate)
+                    .collect(),
+                read_key(key_file)?,
+            )?,
+            _ => config_builder.with_no_client_auth(),
+        };
+        config.enable_sni = self.sni;
+
+        let config = Arc::new(config);
+        let stream = connect(&server, &server_name, &config, &self.retry)?;
+
+        Ok(TlsTarget {
+            server,
+            format: self.format,
+            server_name,
+            config,
+            retry: self.retry,
+            stream: Mutex::new(Some(stream)),
+        })
+    }
+}
+
+impl Target for TlsTarget {
+    fn log(&self, entry: &Entry) -> Result<usize, Box<dyn Error>> {
+        Ok(self.send(&format_entry(self.format, entry)?)?)
+    }
+}
+
+impl Drop for TlsTarget {
+    fn drop(&mut self) {
+        if let Ok(mut stream) = self.stream.lock() {
+            if let Some(stream) = stream.as_mut() {
+                stream.conn.send_close_notify();
+                let _ = stream.flush();
+            }
+        }
+    }
+}
+
+fn connect(
+    server: &str,
+    server_name: &ServerName,
+    config: &Arc<ClientConfig>,
+    retry: &RetryPolicy,
+) -> io::Result<TlsStream> {
+    let connection = ClientConnection::new(config.clone(), server_name.clone())
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+    let socket = retry.connect(server.to_socket_addrs()?, server)?;
+    let mut stream = StreamOwned::new(connection, socket);
+
+    // handshake up front so that certificate problems show up when the
+    // target is opened rather than on the first entry
+    while stream.conn.is_handshaking() {
+        stream.conn.complete_io(&mut stream.sock)?;
+    }
+
+    Ok(stream)
+}
+
+// collectors never send entries back, so anything to read is either TLS
+// housekeeping, such as session tickets, or the collector closing the
+// connection
+fn is_closed(stream: &mut TlsStream) -> bool {
+    if stream.sock.set_nonblocking(true).is_err() {
+        return true;
+    }
+
+    let closed = loop {
+        match stream.conn.read_tls(&mut stream.sock) {
+            Ok(0) => break true,
+            Ok(_) => match stream.conn.process_new_packets() {
+                Ok(state) if state.peer_has_closed() => break true,
+                Ok(_) => continue,
+                Err(_) => break true,
+            },
+            Err(error) => break error.kind() != io::ErrorKind::WouldBlock,
+        }
+    };
+
+    stream.sock.set_nonblocking(false).is_err() || closed
+}
+
+fn write_framed(stream: &mut TlsStream, framed: &[u8]) -> io::Result<()> {
+    stream.write_all(framed)?;
+    stream.flush()
+}
+
+fn read_certs(filename: &str) -> io::Result<Vec<Vec<u8>>> {
+    rustls_pemfile::certs(&mut BufReader::new(File::open(filename)?))
+}
+
+fn read_key(filename: &str) -> io::Result<PrivateKey> {
+    let mut reader = BufReader::new(File::open(filename)?);
+
+    while let Some(item) = rustls_pemfile::read_one(&mut reader)? {
+        match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => return Ok(PrivateKey(key)),
+            _ => continue,
+        }
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("no private key in {}", filename),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Facility, Severity};
+    use rustls::server::AllowAnyAuthenticatedClient;
+    use rustls::{ServerConfig, ServerConnection};
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::thread::JoinHandle;
+    use std::time::{Duration, Instant};
+
+    // a test CA, a certificate it signed for localhost and 127.0.0.1, and an
+    // unrelated CA, all valid for a century
+    fn data(name: &str) -> String {
+        format!("{}/tests/data/tls/{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    fn server_config(client_auth: bool) -> Arc<ServerConfig> {
+        let certs = read_certs(&data("localhost.pem"))
+            .unwrap()
+            .into_iter()
+            .map(Certificate)
+            .collect();
+        let key = read_key(&data("localhost.key")).unwrap();
+        let builder = ServerConfig::builder().with_safe_defaults();
+
+        let builder = if client_auth {
+            let mut roots = RootCertStore::empty();
+            roots.add_parsable_certificates(&read_certs(&data("ca.pem")).unwrap());
+            builder.with_client_cert_verifier(Arc::new(AllowAnyAuthenticatedClient::new(roots)))
+        } else {
+            builder.with_no_client_auth()
+        };
+
+        Arc::new(builder.with_single_cert(certs, key).unwrap())
+    }
+
+    // accepts a single connection and returns everything read from it until
+    // the client closes it
+    fn serve(client_auth: bool) -> (String, JoinHandle<Vec<u8>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = listener.local_addr().unwrap().to_string();
+        let config = server_config(client_auth);
+
+        let received = thread::spawn(move || {
+            let (socket, _) = listener.accept().unwrap();
+            let connection = ServerConnection::new(config).unwrap();
+            let mut stream = StreamOwned::new(connection, socket);
+
+            let mut received = Vec::new();
+            let _ = stream.read_to_end(&mut received);
+            received
+        });
+
+        (server, received)
+    }
+
+    fn entry() -> Entry {
+        Entry::new(Facility::User, Severity::Info, "app", "msgid", "over tls").unwrap()
+    }
+
+    #[test]
+    fn sends_octet_counted_entries() {
+        let (server, received) = serve(false);
+        let target = TlsTarget::builder(&server)
+            .server_name("localhost")
+            .ca_file(&data("ca.pem"))
+            .build()
+            .unwrap();
+
+        let sent = target.log(&entry()).unwrap();
+        drop(target);
+
+        let received = String::from_utf8(received.join().unwrap()).unwrap();
+        let (length, message) = received.split_once(' ').unwrap();
+        assert_eq!(length.parse::<usize>().unwrap(), message.len());
+        assert_eq!(sent, received.len());
+        assert!(message.starts_with("<14>1 "));
+        assert!(message.ends_with(" msgid - over tls"));
+    }
+
+    #[test]
+    fn checks_the_certificate_against_the_ip_address() {
+        let (server, received) = serve(false);
+        let target = TlsTarget::builder(&server)
+            .ca_file(&data("ca.pem"))
+            .build()
+            .unwrap();
+
+        target.log(&entry()).unwrap();
+        drop(target);
+
+        assert!(!received.join().unwrap().is_empty());
+    }
+
+    #[test]
+    fn presents_a_client_certificate() {
+        let (server, received) = serve(true);
+        let target = TlsTarget::builder(&server)
+            .server_name("localhost")
+            .ca_file(&data("ca.pem"))
+            .client_cert(&data("localhost.pem"), &data("localhost.key"))
+            .build()
+            .unwrap();
+
+        target.log(&entry()).unwrap();
+        drop(target);
+
+        assert!(!received.join().unwrap().is_empty());
+    }
+
+    #[test]
+    fn refuses_servers_signed_by_another_ca() {
+        let (server, received) = serve(false);
+        let target = TlsTarget::builder(&server)
+            .server_name("localhost")
+            .ca_file(&data("other-ca.pem"))
+            .build();
+
+        assert!(target.is_err());
+        assert!(received.join().unwrap().is_empty());
+    }
+
+    #[test]
+    fn refuses_a_mismatched_server_name() {
+        let (server, received) = serve(false);
+        let target = TlsTarget::builder(&server)
+            .server_name("collector.example.com")
+            .ca_file(&data("ca.pem"))
+            .build();
+
+        assert!(target.is_err());
+        assert!(received.join().unwrap().is_empty());
+    }
+
+    #[test]
+    fn reconnects_to_a_restarted_collector() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = listener.local_addr().unwrap().to_string();
+        let config = server_config(false);
+        let (closed_sender, closed) = mpsc::channel();
+
+        let received = thread::spawn(move || {
+            // the first collector goes away once it has the first entry
+            let (socket, _) = listener.accept().unwrap();
+            let connection = ServerConnection::new(config.clone()).unwrap();
+            let mut stream = StreamOwned::new(connection, socket);
+            let mut first = Vec::new();
+            let mut buffer = [0u8; 1024];
+            while !String::from_utf8_lossy(&first).contains("first") {
+                let read = stream.read(&mut buffer).unwrap();
+                first.extend_from_slice(&buffer[..read]);
+            }
+            stream.conn.send_close_notify();
+            stream.flush().unwrap();
+            drop(stream);
+            closed_sender.send(()).unwrap();
+
+            let (socket, _) = listener.accept().unwrap();
+            let connection = ServerConnection::new(config).unwrap();
+            let mut stream = StreamOwned::new(connection, socket);
+            let mut second = Vec::new();
+            let _ = stream.read_to_end(&mut second);
+
+            (first, second)
+        });
+
+        let target = TlsTarget::builder(&server)
+            .server_name("localhost")
+            .ca_file(&data("ca.pem"))
+            .build()
+            .unwrap();
+        target.log_message("first").unwrap();
+        closed.recv().unwrap();
+        target.log_message("second").unwrap();
+        drop(target);
+
+        let (first, second) = received.join().unwrap();
+        assert!(String::from_utf8(first).unwrap().ends_with(" first"));
+        assert!(String::from_utf8(second).unwrap().ends_with(" second"));
+    }
+
+    #[test]
+    fn gives_up_on_a_stalled_handshake() {
+        // connections are accepted by the kernel, but nothing ever answers
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = listener.local_addr().unwrap().to_string();
+        let started = Instant::now();
+
+        let target = TlsTarget::builder(&server)
+            .server_name("localhost")
+            .ca_file(&data("ca.pem"))
+            .retry_policy(RetryPolicy {
+                read_timeout: Some(Duration::from_millis(100)),
+                ..RetryPolicy::default()
+            })
+            .build();
+
+        assert!(target.is_err());
+        assert!(started.elapsed() < Duration::from_secs(5));
+        drop(listener);
+    }
+
+    #[test]
+    fn gives_up_after_the_last_retry() {
+        let (server, received) = serve(false);
+        let target = TlsTarget::builder(&server)
+            .server_name("localhost")
+            .ca_file(&data("ca.pem"))
+            .retry_policy(RetryPolicy {
+                max_retries: 2,
+                initial_backoff: Duration::from_millis(1),
+                max_backoff: Duration::from_millis(1),
+                ..RetryPolicy::default()
+            })
+            .build()
+            .unwrap();
+        target.log(&entry()).unwrap();
+
+        // the only collector is gone, so every reconnect is refused
+        *target.stream.lock().unwrap() = None;
+        received.join().unwrap();
+
+        assert!(target.log(&entry()).is_err());
+    }
+}
@@ -0,0 +1,448 @@
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::AsyncWriteExt;
+use tokio::net::{lookup_host, TcpStream, UdpSocket};
+use tokio::sync::Mutex;
+use tokio::task::spawn_blocking;
+use tokio::time::{sleep, timeout};
+
+use crate::format::format_entry;
+use crate::network::{frame, Framing, DEFAULT_TCP_PORT};
+use crate::server::{first_address, unspecified_address, with_default_port};
+use crate::{AsyncTarget, Entry, Format, RetryPolicy, Target};
+
+pub type LogFuture<'a> = Pin<Box<dyn Future<Output = io::Result<usize>> + Send + 'a>>;
+
+// the asynchronous counterpart of Target, for targets that can be logged to
+// from within a tokio runtime without stalling it
+pub trait TokioTarget {
+    fn log<'a>(&'a self, entry: &'a Entry) -> LogFuture<'a>;
+}
+
+pub async fn add_entry_async(
+    target: &(impl TokioTarget + ?Sized),
+    entry: &Entry,
+) -> io::Result<usize> {
+    target.log(entry).await
+}
+
+// forwards entries to a blocking Target through the queue and worker thread
+// of an AsyncTarget, so that file, socket and other targets can be used from
+// async code
+//
+// dropping the last handle drains the queue the way dropping the AsyncTarget
+// does, which blocks, so async code should call shutdown first
+#[derive(Clone)]
+pub struct AsyncTargetHandle {
+    target: Arc<AsyncTarget>,
+}
+
+impl AsyncTargetHandle {
+    // up to capacity entries are queued before logging waits for the worker
+    pub fn new(target: impl Target + Send + 'static, capacity: usize) -> io::Result<Self> {
+        let target = AsyncTarget::builder(target)
+            .capacity(capacity)
+            .build()
+            .map_err(to_io_error)?;
+
+        Ok(AsyncTargetHandle::from(target))
+    }
+
+    // waits until every entry sent so far has been logged
+    pub async fn flush(&self) -> io::Result<()> {
+        let target = self.target.clone();
+        spawn_blocking(move || target.flush().map_err(to_io_error)).await?
+    }
+
+    // logs everything already sent and then stops the worker, after which
+    // logging through any handle to it fails
+    pub async fn shutdown(&self) -> io::Result<()> {
+        self.target.close();
+        self.flush().await
+    }
+}
+
+impl From<AsyncTarget> for AsyncTargetHandle {
+    fn from(target: AsyncTarget) -> Self {
+        AsyncTargetHandle {
+            target: Arc::new(target),
+        }
+    }
+}
+
+impl TokioTarget for AsyncTargetHandle {
+    fn log<'a>(&'a self, entry: &'a Entry) -> LogFuture<'a> {
+        Box::pin(async move {
+            let entry = entry.try_clone().map_err(to_io_error)?;
+
+            // only an entry that has to wait for room is handed to a blocking
+            // thread, so that the runtime itself never waits on the worker
+            if let Some(entry) = self.target.enqueue(entry, false).map_err(to_io_error)? {
+                let target = self.target.clone();
+                spawn_blocking(move || target.enqueue(entry, true).map_err(to_io_error)).await??;
+            }
+
+            Ok(0)
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Protocol {
+    Tcp,
+    Udp,
+}
+
+pub struct TokioNetworkTarget {
+    server: String,
+    framing: Framing,
+    format: Format,
+    retry: RetryPolicy,
+    connection: Connection,
+    shut_down: AtomicBool,
+}
+
+enum Connection {
+    // empty once the connection is lost, until the next entry reconnects
+    Tcp(Mutex<Option<TcpStream>>),
+    Udp(UdpSocket),
+}
+
+pub struct TokioNetworkTargetBuilder {
+    server: String,
+    protocol: Protocol,
+    framing: Framing,
+    format: Format,
+    retry: RetryPolicy,
+}
+
+impl TokioNetworkTarget {
+    pub async fn new(server: &str) -> io::Result<Self> {
+        TokioNetworkTarget::builder(server).build().await
+    }
+
+    pub fn builder(server: &str) -> TokioNetworkTargetBuilder {
+        TokioNetworkTargetBuilder {
+            server: server.to_string(),
+            protocol: Protocol::Tcp,
+            framing: Framing::NonTransparent,
+            format: Format::Rfc5424,
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    pub fn server(&self) -> &str {
+        &self.server
+    }
+
+    pub fn protocol(&self) -> Protocol {
+        match self.connection {
+            Connection::Tcp(_) => Protocol::Tcp,
+            Connection::Udp(_) => Protocol::Udp,
+        }
+    }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        self.retry
+    }
+
+    // flushes and closes the connection, after which logging fails
+    pub async fn shutdown(&self) -> io::Result<()> {
+        self.shut_down.store(true, Ordering::SeqCst);
+
+        match &self.connection {
+            Connection::Tcp(stream) => match stream.lock().await.take() {
+                Some(mut stream) => stream.shutdown().await,
+                None => Ok(()),
+            },
+            Connection::Udp(_) => Ok(()),
+        }
+    }
+
+    async fn send(&self, message: &str) -> io::Result<usize> {
+        let stream = match &self.connection {
+            Connection::Tcp(stream) => stream,
+            // each datagram holds exactly one message, so it needs no framing
+            Connection::Udp(socket) => return socket.send(message.as_bytes()).await,
+        };
+        let framed = frame(self.framing, message);
+        let mut attempt = 0;
+
+        loop {
+            {
+                let mut stream = stream.lock().await;
+
+                let error = match self.write(&mut stream, framed.as_bytes()).await {
+                    Ok(()) => return Ok(framed.len()),
+                    Err(error) => error,
+                };
+
+                if attempt >= self.retry.max_retries || self.shut_down.load(Ordering::SeqCst) {
+                    return Err(error);
+                }
+            }
+
+            // the connection is unlocked while waiting, so that other tasks
+            // can still get their entry through
+            sleep(self.retry.backoff(attempt)).await;
+            attempt += 1;
+        }
+    }
+
+    async fn write(&self, stream: &mut Option<TcpStream>, framed: &[u8]) -> io::Result<()> {
+        if self.shut_down.load(Ordering::SeqCst) {
+            return Err(shut_down());
+        }
+
+        // a collector that went away is usually only noticed on the write
+        // after the one that was lost, so check for it up front
+        if stream.as_ref().map_or(false, is_closed) {
+            *stream = None;
+        }
+
+        if stream.is_none() {
+            *stream = Some(connect(&self.server, &self.retry).await?);
+        }
+
+        let result = within(
+            self.retry.write_timeout,
+            stream.as_mut().unwrap().write_all(framed),
+        )
+        .await;
+        if result.is_err() {
+            *stream = None;
+        }
+
+        result
+    }
+}
+
+impl TokioNetworkTargetBuilder {
+    pub fn protocol(mut self, protocol: Protocol) -> Self {
+        self.protocol = protocol;
+        self
+    }
+
+    // only used for TCP
+    pub fn framing(mut self, framing: Framing) -> Self {
+        self.framing = framing;
+        self
+    }
+
+    pub fn format(mut self, format: Format) -> Self {
+        self.format = format;
+        self
+    }
+
+    // only used for TCP, as there is no way to tell a UDP send was lost
+    pub fn retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub async fn build(self) -> io::Result<TokioNetworkTarget> {
+        let server = with_default_port(&self.server, DEFAULT_TCP_PORT);
+        let connection = match self.protocol {
+            Protocol::Tcp => {
+                Connection::Tcp(Mutex::new(Some(connect(&server, &self.retry).await?)))
+            }
+            Protocol::Udp => {
+                let address = first_address(lookup_host(&server).await?, &server)?;
+                let socket = UdpSocket::bind(unspecified_address(&address)).await?;
+                socket.connect(address).await?;
+                Connection::Udp(socket)
+            }
+        };
+
+        Ok(TokioNetworkTarget {
+            server,
+            framing: self.framing,
+            format: self.format,
+            retry: self.retry,
+            connection,
+            shut_down: AtomicBool::new(false),
+        })
+    }
+}
+
+impl TokioTarget for TokioNetworkTarget {
+    fn log<'a>(&'a self, entry: &'a Entry) -> LogFuture<'a> {
+        Box::pin(async move {
+            let message = format_entry(self.format, entry).map_err(to_io_error)?;
+            self.send(&message).await
+        })
+    }
+}
+
+// collectors never send anything back, so a readable socket means the
+// connection was closed or reset
+//
+// tokio only learns a socket is readable once the runtime gets around to
+// polling it, so a duplicate of it is peeked at directly instead, which is
+// already non-blocking as the flag is shared with the original
+fn is_closed(stream: &TcpStream) -> bool {
+    #[cfg(unix)]
+    let duplicate = std::os::fd::AsFd::as_fd(stream).try_clone_to_owned();
+    #[cfg(windows)]
+    let duplicate = std::os::windows::io::AsSocket::as_socket(stream).try_clone_to_owned();
+
+    let duplicate = match duplicate {
+        Ok(duplicate) => std::net::TcpStream::from(duplicate),
+        Err(_) => return true,
+    };
+
+    match duplicate.peek(&mut [0u8; 1]) {
+        Ok(0) => true,
+        Ok(_) => false,
+        Err(error) => error.kind() != io::ErrorKind::WouldBlock,
+    }
+}
+
+async fn connect(server: &str, retry: &RetryPolicy) -> io::Result<TcpStream> {
+    within(retry.connect_timeout, TcpStream::connect(server)).await
+}
+
+async fn within<T>(
+    duration: Option<Duration>,
+    future: impl Future<Output = io::Result<T>>,
+) -> io::Result<T> {
+    match duration {
+        Some(duration) => timeout(duration, future)
+            .await
+            .unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::TimedOut, "timed out"))),
+        None => future.await,
+    }
+}
+
+fn shut_down() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::BrokenPipe,
+        "the asynchronous target has shut down",
+    )
+}
+
+fn to_io_error(error: Box<dyn std::error::Error>) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, error.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Facility, Severity};
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::thread;
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    fn entry(message: &str) -> Entry {
+        Entry::new(Facility::User, Severity::Info, "app", "msgid", message).unwrap()
+    }
+
+    struct Recording(Arc<std::sync::Mutex<Vec<String>>>);
+
+    impl Target for Recording {
+        fn log(&self, entry: &Entry) -> Result<usize, Box<dyn std::error::Error>> {
+            thread::sleep(Duration::from_millis(1));
+            self.0.lock().unwrap().push(entry.get_message()?);
+            Ok(0)
+        }
+    }
+
+    #[test]
+    fn handle_logs_through_the_async_target() {
+        let messages = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let handle = AsyncTargetHandle::new(Recording(messages.clone()), 1).unwrap();
+
+        block_on(async {
+            // a queue this small has most entries wait for room
+            for i in 0..20 {
+                handle.log(&entry(&i.to_string())).await.unwrap();
+            }
+            handle.flush().await.unwrap();
+
+            let expected: Vec<String> = (0..20).map(|i| i.to_string()).collect();
+            assert_eq!(*messages.lock().unwrap(), expected);
+
+            handle.shutdown().await.unwrap();
+            assert!(handle.log(&entry("late")).await.is_err());
+        });
+    }
+
+    #[test]
+    fn reconnects_to_a_restarted_collector() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = listener.local_addr().unwrap().to_string();
+
+        block_on(async {
+            let target = TokioNetworkTarget::builder(&server)
+                .framing(Framing::OctetCounting)
+                .build()
+                .await
+                .unwrap();
+
+            // the collector reads the first entry and then goes away
+            target.send("before").await.unwrap();
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut first = [0u8; 8];
+            stream.read_exact(&mut first).unwrap();
+            assert_eq!(&first, b"6 before");
+            drop(stream);
+            drop(listener);
+
+            let listener = TcpListener::bind(&server).unwrap();
+            let received = thread::spawn(move || {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut received = Vec::new();
+                stream.read_to_end(&mut received).unwrap();
+                received
+            });
+
+            // wait for the close to reach the target's side of the connection
+            sleep(Duration::from_millis(50)).await;
+            target.send("after").await.unwrap();
+            target.shutdown().await.unwrap();
+
+            assert_eq!(received.join().unwrap(), b"5 after");
+        });
+    }
+
+    #[test]
+    fn gives_up_after_the_last_retry() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = listener.local_addr().unwrap().to_string();
+
+        block_on(async {
+            let target = TokioNetworkTarget::builder(&server)
+                .retry_policy(RetryPolicy {
+                    max_retries: 2,
+                    initial_backoff: Duration::from_millis(50),
+                    max_backoff: Duration::from_millis(50),
+                    ..RetryPolicy::default()
+                })
+                .build()
+                .await
+                .unwrap();
+            drop(listener);
+            if let Connection::Tcp(stream) = &target.connection {
+                stream.lock().await.take();
+            }
+
+            // two backoffs of at least half the delay each
+            let started = std::time::Instant::now();
+            assert!(target.send("lost").await.is_err());
+            assert!(started.elapsed() >= Duration::from_millis(50));
+        });
+    }
+}
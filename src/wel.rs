@@ -6,11 +6,13 @@ use stumpless_sys::{
 use std::error::Error;
 use std::ffi::CString;
 
+use crate::target::sealed::Sealed;
+use crate::target::TargetPointer;
+use crate::NativeTarget;
 use crate::StumplessError;
-use crate::Target;
 
 pub struct WelTarget {
-    target: *mut stumpless_target,
+    target: TargetPointer,
 }
 
 impl WelTarget {
@@ -21,21 +23,25 @@ impl WelTarget {
         if wel_target.is_null() {
             Err(Box::new(StumplessError))
         } else {
-            Ok(WelTarget { target: wel_target })
+            Ok(WelTarget {
+                target: TargetPointer(wel_target),
+            })
         }
     }
 }
 
-impl Target for WelTarget {
+impl Sealed for WelTarget {}
+
+impl NativeTarget for WelTarget {
     fn get_pointer(&self) -> *mut stumpless_target {
-        self.target
+        self.target.0
     }
 }
 
 impl Drop for WelTarget {
     fn drop(&mut self) {
         unsafe {
-            stumpless_close_wel_target(self.target);
+            stumpless_close_wel_target(self.target.0);
         }
     }
 }
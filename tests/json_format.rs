@@ -0,0 +1,133 @@
+use std::process::{Command, ExitStatus};
+#[cfg(any(feature = "network", feature = "tls"))]
+use std::{io::Read, net::TcpListener, thread};
+
+fn stumpless() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_stumpless"))
+}
+
+// the status and process id of the finished command, which is the procid of
+// the entries it logged
+fn run(command: &mut Command) -> (ExitStatus, u32) {
+    let mut child = command.spawn().unwrap();
+    let pid = child.id();
+    (child.wait().unwrap(), pid)
+}
+
+// everything but the timestamp and hostname, which change between runs and
+// hosts, is fixed by the command line and process id
+#[track_caller]
+fn assert_json_line(line: &str, pid: u32) {
+    assert!(line.starts_with("{\"timestamp\":\""), "{}", line);
+    assert!(
+        line.contains(
+            "\",\"facility\":\"user\",\"facility_code\":1,\"severity\":\"alert\",\"severity_code\":1,\"hostname\":\""
+        ),
+        "{}",
+        line
+    );
+    assert!(
+        line.ends_with(&format!(
+            "\",\"app_name\":\"app_name\",\"procid\":\"{}\",\"msgid\":\"msgid\",\"message\":\"hello \\\"json\\\"\",\"structured_data\":{{}}}}",
+            pid
+        )),
+        "{}",
+        line
+    );
+}
+
+#[test]
+fn writes_json_lines_to_a_file() {
+    let path = std::env::temp_dir().join(format!("stumpless-json-{}.log", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    let (status, pid) = run(stumpless()
+        .args(["--format", "json", "--log-file"])
+        .arg(&path)
+        .arg("hello \"json\""));
+    let contents = std::fs::read_to_string(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+
+    assert!(status.success());
+    let lines: Vec<&str> = contents.lines().collect();
+    assert_eq!(lines.len(), 1, "{}", contents);
+    assert_json_line(lines[0], pid);
+}
+
+#[cfg(feature = "network")]
+#[test]
+fn sends_json_lines_over_tcp() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let server = listener.local_addr().unwrap().to_string();
+    let received = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut received = String::new();
+        stream.read_to_string(&mut received).unwrap();
+        received
+    });
+
+    let (status, pid) =
+        run(stumpless().args(["--format", "json", "--tcp4", &server, "hello \"json\""]));
+    let received = received.join().unwrap();
+
+    assert!(status.success());
+    // each entry is followed by a newline, which JSON lines never contain
+    assert_json_line(received.strip_suffix('\n').unwrap(), pid);
+}
+
+#[cfg(feature = "tls")]
+#[test]
+fn sends_json_lines_over_tls() {
+    use rustls::{Certificate, PrivateKey, ServerConfig, ServerConnection, StreamOwned};
+    use std::fs::File;
+    use std::io::BufReader;
+    use std::sync::Arc;
+
+    let data = |name: &str| format!("{}/tests/data/tls/{}", env!("CARGO_MANIFEST_DIR"), name);
+    let certs = rustls_pemfile::certs(&mut BufReader::new(
+        File::open(data("localhost.pem")).unwrap(),
+    ))
+    .unwrap()
+    .into_iter()
+    .map(Certificate)
+    .collect();
+    let key = rustls_pemfile::pkcs8_private_keys(&mut BufReader::new(
+        File::open(data("localhost.key")).unwrap(),
+    ))
+    .unwrap()
+    .remove(0);
+    let config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, PrivateKey(key))
+        .unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let server = listener.local_addr().unwrap().to_string();
+    let received = thread::spawn(move || {
+        let (socket, _) = listener.accept().unwrap();
+        let connection = ServerConnection::new(Arc::new(config)).unwrap();
+        let mut stream = StreamOwned::new(connection, socket);
+        let mut received = Vec::new();
+        let _ = stream.read_to_end(&mut received);
+        String::from_utf8(received).unwrap()
+    });
+
+    let (status, pid) = run(stumpless()
+        .args([
+            "--format",
+            "json",
+            "--tls-server",
+            &server,
+            "--tls-ca",
+            &data("ca.pem"),
+        ])
+        .arg("hello \"json\""));
+    let received = received.join().unwrap();
+
+    assert!(status.success());
+    // entries over TLS are octet counted
+    let (length, line) = received.split_once(' ').unwrap();
+    assert_eq!(length.parse::<usize>().unwrap(), line.len());
+    assert_json_line(line, pid);
+}
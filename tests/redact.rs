@@ -0,0 +1,64 @@
+use std::process::Command;
+
+fn stumpless() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_stumpless"))
+}
+
+fn log_to_file(name: &str, args: &[&str]) -> String {
+    let path = std::env::temp_dir().join(format!(
+        "stumpless-redact-{}-{}.log",
+        name,
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&path);
+
+    let status = stumpless()
+        .arg("--log-file")
+        .arg(&path)
+        .args(args)
+        .status()
+        .unwrap();
+    let contents = std::fs::read_to_string(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+
+    assert!(status.success());
+    contents
+}
+
+#[test]
+fn redacts_builtin_secrets() {
+    let contents = log_to_file("builtin", &["--redact", "login", "password=hunter2"]);
+
+    assert!(
+        contents.ends_with("login password=[REDACTED]\n"),
+        "{}",
+        contents
+    );
+}
+
+#[test]
+fn redacts_given_patterns() {
+    let contents = log_to_file(
+        "pattern",
+        &[
+            "--redact-pattern",
+            r"secret-\d+",
+            "--redact-pattern",
+            "hidden",
+            "secret-42 is hidden, password=hunter2",
+        ],
+    );
+
+    assert!(
+        contents.ends_with("[REDACTED] is [REDACTED], password=[REDACTED]\n"),
+        "{}",
+        contents
+    );
+}
+
+#[test]
+fn leaves_messages_alone_without_redact() {
+    let contents = log_to_file("off", &["password=hunter2"]);
+
+    assert!(contents.ends_with("password=hunter2\n"), "{}", contents);
+}